@@ -7,10 +7,32 @@ fn main() {
     println!("cargo:rerun-if-changed=proto/dd_metric.proto");
     println!("cargo:rerun-if-changed=proto/google/pubsub/v1/pubsub.proto");
 
+    // Reported in the tracer metadata headers of the export requests.
+    let rustc = std::env::var("RUSTC").unwrap_or_else(|_| "rustc".to_string());
+    let rustc_version = std::process::Command::new(rustc)
+        .arg("--version")
+        .output()
+        .ok()
+        .and_then(|output| String::from_utf8(output.stdout).ok())
+        .and_then(|version| version.split_whitespace().nth(1).map(str::to_string))
+        .unwrap_or_default();
+    println!("cargo:rustc-env=DD_RUSTC_VERSION={rustc_version}");
+    println!(
+        "cargo:rustc-env=DD_TARGET={}",
+        std::env::var("TARGET").unwrap_or_default()
+    );
+
     let mut prost_build = prost_build::Config::new();
     prost_build.btree_map(["."]);
+    prost_build.type_attribute(
+        ".dd_trace",
+        "#[cfg_attr(feature = \"debug-payload\", derive(serde::Serialize))]",
+    );
 
+    // Only the messages are used, the gRPC clients would pull tonic in.
     tonic_build::configure()
+        .build_client(false)
+        .build_server(false)
         .compile_with_config(
             prost_build,
             &[
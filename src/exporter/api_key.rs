@@ -0,0 +1,34 @@
+use async_trait::async_trait;
+use std::fmt;
+use std::sync::Arc;
+
+use super::Error;
+
+/// Where the API key comes from when it may change while the exporter runs, e.g. a Workers
+/// secret or a key management service, see
+/// [`DatadogPipelineBuilder::with_api_key_provider`](super::DatadogPipelineBuilder::with_api_key_provider).
+///
+/// The key is asked for on every export, providers fetching it remotely should cache it. On
+/// Workers, bindings which aren't `Send` can be kept in a `send_wrapper::SendWrapper`. Outside of
+/// Workers the future of [`get_key`](Self::get_key) is `Send`, as the exports run on the threads
+/// of the async runtime.
+#[cfg_attr(target_arch = "wasm32", async_trait(?Send))]
+#[cfg_attr(not(target_arch = "wasm32"), async_trait)]
+pub trait ApiKeyProvider: Send + Sync {
+    /// The current API key.
+    ///
+    /// # Errors
+    ///
+    /// If the key can't be retrieved, the export then fails with this error.
+    async fn get_key(&self) -> Result<String, Error>;
+}
+
+/// The provider given to [`DatadogPipelineBuilder::with_api_key_provider`](super::DatadogPipelineBuilder::with_api_key_provider).
+#[derive(Clone)]
+pub(crate) struct ApiKeyProviderHandle(pub(crate) Arc<dyn ApiKeyProvider>);
+
+impl fmt::Debug for ApiKeyProviderHandle {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("ApiKeyProvider")
+    }
+}
@@ -0,0 +1,96 @@
+use crate::dd_proto;
+
+/// Most spans kept for reuse, about a default flush.
+const MAX_POOLED_SPANS: usize = 500;
+/// Most span vectors kept for reuse, one per trace of an export.
+const MAX_POOLED_SPAN_VECS: usize = 64;
+
+/// Allocations of the previous exports, reused by the next ones so repeated flushes in a hot
+/// isolate don't go back to the allocator for every span.
+///
+/// The spans keep the capacity of their strings. `BTreeMap`s don't keep allocations once
+/// cleared, the meta entries are allocated anew.
+#[derive(Debug, Default)]
+pub(crate) struct ExportArena {
+    spans: Vec<dd_proto::Span>,
+    span_vecs: Vec<Vec<dd_proto::Span>>,
+}
+
+impl ExportArena {
+    /// A cleared span, whose fields must all be assigned.
+    pub(crate) fn span(&mut self) -> dd_proto::Span {
+        self.spans.pop().unwrap_or_default()
+    }
+
+    /// An empty vector of spans.
+    pub(crate) fn span_vec(&mut self) -> Vec<dd_proto::Span> {
+        self.span_vecs.pop().unwrap_or_default()
+    }
+
+    /// Number of spans kept for reuse.
+    #[cfg(test)]
+    pub(crate) fn pooled_spans(&self) -> usize {
+        self.spans.len()
+    }
+
+    /// Keep the spans of exported `chunks` and their vectors for the next exports.
+    pub(crate) fn recycle(&mut self, chunks: impl IntoIterator<Item = dd_proto::TraceChunk>) {
+        for mut chunk in chunks {
+            for mut span in chunk.spans.drain(..) {
+                if self.spans.len() >= MAX_POOLED_SPANS {
+                    break;
+                }
+                span.service.clear();
+                span.name.clear();
+                span.resource.clear();
+                span.r#type.clear();
+                span.meta.clear();
+                span.metrics.clear();
+                span.meta_struct.clear();
+                self.spans.push(span);
+            }
+            if self.span_vecs.len() < MAX_POOLED_SPAN_VECS {
+                self.span_vecs.push(chunk.spans);
+            }
+        }
+    }
+}
+
+/// Overwrite `target` with `value`, keeping its capacity.
+pub(crate) fn assign(target: &mut String, value: &str) {
+    target.clear();
+    target.push_str(value);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::BTreeMap;
+
+    #[test]
+    fn test_recycle() {
+        let mut arena = ExportArena::default();
+        let mut spans = arena.span_vec();
+        spans.push(dd_proto::Span {
+            name: "a".repeat(100),
+            meta: BTreeMap::from([("key".to_string(), "value".to_string())]),
+            ..Default::default()
+        });
+        arena.recycle([dd_proto::TraceChunk {
+            spans,
+            ..Default::default()
+        }]);
+
+        let spans = arena.span_vec();
+        assert!(spans.is_empty());
+        assert!(spans.capacity() >= 1);
+
+        let mut span = arena.span();
+        assert!(span.name.is_empty());
+        assert!(span.meta.is_empty());
+        assert!(span.name.capacity() >= 100);
+        assign(&mut span.name, "graphql");
+        assert_eq!(span.name, "graphql");
+        assert_eq!(arena.span(), dd_proto::Span::default());
+    }
+}
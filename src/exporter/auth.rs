@@ -0,0 +1,76 @@
+use std::borrow::Cow;
+
+use super::transport::ExportRequest;
+use super::DEFAULT_DD_API_KEY_HEADER;
+
+/// How the API key is sent to the endpoint, see
+/// [`DatadogPipelineBuilder::with_auth`](super::DatadogPipelineBuilder::with_auth).
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub enum AuthScheme {
+    /// In the `DD-Api-Key` header, as the Datadog intake expects it.
+    #[default]
+    DatadogApiKey,
+    /// As a bearer token in the `Authorization` header, e.g. for an authenticating proxy.
+    Bearer,
+    /// As is in the header with the given name.
+    Header(String),
+}
+
+impl AuthScheme {
+    /// Name of the header holding the key.
+    pub(crate) fn header_name(&self) -> Cow<'static, str> {
+        match self {
+            AuthScheme::DatadogApiKey => Cow::Borrowed(DEFAULT_DD_API_KEY_HEADER),
+            AuthScheme::Bearer => Cow::Borrowed("Authorization"),
+            AuthScheme::Header(name) => Cow::Owned(name.clone()),
+        }
+    }
+
+    /// Whether `name` is the header holding the key.
+    pub(crate) fn is_auth_header(&self, name: &str) -> bool {
+        name.eq_ignore_ascii_case(&self.header_name())
+    }
+
+    /// Add `key` to `request`.
+    pub(crate) fn authenticate(&self, request: ExportRequest, key: &str) -> ExportRequest {
+        let value = match self {
+            AuthScheme::Bearer => format!("Bearer {key}"),
+            AuthScheme::DatadogApiKey | AuthScheme::Header(_) => key.to_string(),
+        };
+        request.header(self.header_name(), value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn auth_header(auth: &AuthScheme) -> (String, String) {
+        let request = auth.authenticate(
+            ExportRequest::post("https://example.com".to_string(), Vec::new()),
+            "secret",
+        );
+        let (name, value) = &request.headers[0];
+        (name.to_string(), value.clone())
+    }
+
+    #[test]
+    fn test_authenticate() {
+        assert_eq!(
+            auth_header(&AuthScheme::DatadogApiKey),
+            ("DD-Api-Key".to_string(), "secret".to_string())
+        );
+        assert_eq!(
+            auth_header(&AuthScheme::Bearer),
+            ("Authorization".to_string(), "Bearer secret".to_string())
+        );
+        assert_eq!(
+            auth_header(&AuthScheme::Header("X-Proxy-Key".to_string())),
+            ("X-Proxy-Key".to_string(), "secret".to_string())
+        );
+
+        assert!(AuthScheme::DatadogApiKey.is_auth_header("dd-api-key"));
+        assert!(AuthScheme::Header("X-Proxy-Key".to_string()).is_auth_header("x-proxy-key"));
+        assert!(!AuthScheme::Bearer.is_auth_header("DD-Api-Key"));
+    }
+}
@@ -0,0 +1,53 @@
+use opentelemetry::trace::TraceError;
+use serde::Serialize;
+
+use super::ApiVersion;
+
+/// Log `payload` as JSON, to see exactly what is sent to Datadog.
+pub(crate) fn log_payload(api_version: ApiVersion, payload: &impl Serialize) {
+    match serde_json::to_string(payload) {
+        Ok(json) => log(&format!("Datadog {api_version:?} payload: {json}")),
+        Err(err) => opentelemetry::global::handle_error(TraceError::Other(Box::new(err))),
+    }
+}
+
+#[cfg(feature = "worker")]
+fn log(message: &str) {
+    worker::console_log!("{}", message);
+}
+
+#[cfg(not(feature = "worker"))]
+fn log(message: &str) {
+    eprintln!("{message}");
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::dd_proto;
+    use std::collections::BTreeMap;
+
+    #[test]
+    fn test_payload_json() {
+        let payload = dd_proto::TracePayload {
+            env: "production".to_string(),
+            tracer_payloads: vec![dd_proto::TracerPayload {
+                chunks: vec![dd_proto::TraceChunk {
+                    spans: vec![dd_proto::Span {
+                        service: "gateway".to_string(),
+                        meta: BTreeMap::from([("http.method".to_string(), "GET".to_string())]),
+                        ..Default::default()
+                    }],
+                    ..Default::default()
+                }],
+                ..Default::default()
+            }],
+            ..Default::default()
+        };
+
+        let json = serde_json::to_value(&payload).unwrap();
+        assert_eq!(json["env"], "production");
+        let span = &json["tracer_payloads"][0]["chunks"][0]["spans"][0];
+        assert_eq!(span["service"], "gateway");
+        assert_eq!(span["meta"]["http.method"], "GET");
+    }
+}
@@ -0,0 +1,240 @@
+use opentelemetry::trace::TraceError;
+use prost::Message;
+use std::time::Duration;
+use worker::{ListOptions, Request, Response, State, Storage};
+
+use super::{time, DatadogExporter, DatadogPipelineBuilder};
+use crate::dd_proto;
+
+/// Prefix of the storage keys of the buffered chunks.
+const CHUNK_PREFIX: &str = "chunk/";
+/// Largest chunk kept in storage, whose values are limited to 128 KiB once serialized. Larger
+/// chunks are sent right away, they make a large enough request on their own.
+const MAX_STORED_CHUNK_SIZE: usize = 32 * 1024;
+/// Most keys deleted at once by Durable Object storage.
+const MAX_DELETED_KEYS: usize = 128;
+
+/// The Durable Object side of [`DatadogPipelineBuilder::with_durable_object`]: buffers the trace
+/// chunks Workers send to it in its storage and exports them together on an alarm, turning many
+/// small intake requests into a few larger ones.
+///
+/// The Durable Object forwards its `fetch` and `alarm` handlers:
+///
+/// ```ignore
+/// #[durable_object]
+/// pub struct TraceAggregator {
+///     aggregator: DurableObjectAggregator,
+/// }
+///
+/// #[durable_object]
+/// impl DurableObject for TraceAggregator {
+///     fn new(state: State, env: Env) -> Self {
+///         let pipeline = new_pipeline()
+///             .with_api_key(env.secret("DD_API_KEY").ok().map(|key| key.to_string()));
+///         let aggregator = DurableObjectAggregator::new(&state, pipeline, Duration::from_secs(10))
+///             .expect("valid pipeline");
+///         Self { aggregator }
+///     }
+///
+///     async fn fetch(&mut self, req: Request) -> Result<Response> {
+///         self.aggregator.fetch(req).await
+///     }
+///
+///     async fn alarm(&mut self) -> Result<Response> {
+///         self.aggregator.alarm().await
+///     }
+/// }
+/// ```
+///
+/// Chunks are deleted once exported, a failed export is retried on the next alarm, which may
+/// send the requests which went through again.
+pub struct DurableObjectAggregator {
+    storage: Storage,
+    exporter: DatadogExporter,
+    interval: Duration,
+    sequence: u64,
+}
+
+impl std::fmt::Debug for DurableObjectAggregator {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("DurableObjectAggregator")
+            .field("exporter", &self.exporter)
+            .field("interval", &self.interval)
+            .finish_non_exhaustive()
+    }
+}
+
+impl DurableObjectAggregator {
+    /// Export the chunks received by the Durable Object of `state` with the exporter of
+    /// `pipeline`, `interval` after the first chunk of each batch arrived.
+    ///
+    /// # Errors
+    ///
+    /// If the exporter of `pipeline` can't be built.
+    pub fn new(
+        state: &State,
+        pipeline: DatadogPipelineBuilder,
+        interval: Duration,
+    ) -> Result<Self, TraceError> {
+        Ok(DurableObjectAggregator {
+            storage: state.storage(),
+            exporter: pipeline.build_exporter()?,
+            interval,
+            sequence: 0,
+        })
+    }
+
+    /// Handle a `v0.2` payload sent by a Worker: store its chunks and schedule their export.
+    ///
+    /// # Errors
+    ///
+    /// If the storage is unavailable.
+    pub async fn fetch(&mut self, mut req: Request) -> worker::Result<Response> {
+        let payload = match dd_proto::TracePayload::decode(&req.bytes().await?[..]) {
+            Ok(payload) => payload,
+            Err(err) => return Response::error(format!("invalid trace payload: {err}"), 400),
+        };
+
+        let (stored, oversized) = partition_chunks(payload);
+        for encoded in stored {
+            let key = chunk_key(time::to_unix_millis(time::now()), self.sequence);
+            self.sequence += 1;
+            self.storage.put(&key, encoded).await?;
+        }
+
+        if self.storage.get_alarm().await?.is_none() {
+            self.schedule_alarm().await?;
+        }
+
+        if !oversized.is_empty() {
+            if let Err(err) = self.exporter.export_chunks(oversized).await {
+                return Response::error(err.to_string(), 502);
+            }
+        }
+
+        Response::empty()
+    }
+
+    /// Export the stored chunks, deleting them once Datadog accepted them.
+    ///
+    /// # Errors
+    ///
+    /// If the storage is unavailable or the export failed, the chunks are then exported again by
+    /// the next alarm.
+    pub async fn alarm(&mut self) -> worker::Result<Response> {
+        let stored = self
+            .storage
+            .list_with_options(ListOptions::new().prefix(CHUNK_PREFIX))
+            .await?;
+
+        let mut keys = Vec::new();
+        let mut chunks = Vec::new();
+        for key in stored.keys() {
+            let Some(key) = key?.as_string() else {
+                continue;
+            };
+            let encoded: Vec<u8> = self.storage.get(&key).await?;
+            match dd_proto::TraceChunk::decode(&encoded[..]) {
+                Ok(chunk) => chunks.push(chunk),
+                Err(err) => opentelemetry::global::handle_error(TraceError::Other(Box::new(err))),
+            }
+            keys.push(key);
+        }
+
+        if !chunks.is_empty() {
+            if let Err(err) = self.exporter.export_chunks(chunks).await {
+                self.schedule_alarm().await?;
+                return Err(worker::Error::RustError(err.to_string()));
+            }
+        }
+
+        for keys in keys.chunks(MAX_DELETED_KEYS) {
+            self.storage.delete_multiple(keys.to_vec()).await?;
+        }
+
+        Response::empty()
+    }
+
+    /// Set the alarm `interval` from now, as a unix timestamp in milliseconds.
+    async fn schedule_alarm(&self) -> worker::Result<()> {
+        let interval = i64::try_from(self.interval.as_millis()).unwrap_or(i64::MAX);
+        let now = i64::try_from(time::to_unix_millis(time::now())).unwrap_or(i64::MAX);
+        self.storage.set_alarm(now.saturating_add(interval)).await
+    }
+}
+
+/// The encoded chunks of `payload` small enough to be stored, and the larger ones.
+fn partition_chunks(payload: dd_proto::TracePayload) -> (Vec<Vec<u8>>, Vec<dd_proto::TraceChunk>) {
+    let mut stored = Vec::new();
+    let mut oversized = Vec::new();
+    for chunk in payload
+        .tracer_payloads
+        .into_iter()
+        .flat_map(|tracer| tracer.chunks)
+    {
+        let encoded = chunk.encode_to_vec();
+        if encoded.len() > MAX_STORED_CHUNK_SIZE {
+            oversized.push(chunk);
+        } else {
+            stored.push(encoded);
+        }
+    }
+    (stored, oversized)
+}
+
+/// The storage key of a chunk, listed in the order the chunks arrived.
+fn chunk_key(unix_millis: u64, sequence: u64) -> String {
+    format!("{CHUNK_PREFIX}{unix_millis:020}-{sequence:020}")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn chunk(meta_size: usize) -> dd_proto::TraceChunk {
+        dd_proto::TraceChunk {
+            spans: vec![dd_proto::Span {
+                meta: [("graphql.query".to_string(), "a".repeat(meta_size))].into(),
+                ..Default::default()
+            }],
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_partition_chunks() {
+        let payload = dd_proto::TracePayload {
+            tracer_payloads: vec![
+                dd_proto::TracerPayload {
+                    chunks: vec![chunk(10), chunk(MAX_STORED_CHUNK_SIZE)],
+                    ..Default::default()
+                },
+                dd_proto::TracerPayload {
+                    chunks: vec![chunk(20)],
+                    ..Default::default()
+                },
+            ],
+            ..Default::default()
+        };
+
+        let (stored, oversized) = partition_chunks(payload);
+        assert_eq!(
+            stored,
+            vec![chunk(10).encode_to_vec(), chunk(20).encode_to_vec()]
+        );
+        assert_eq!(oversized, vec![chunk(MAX_STORED_CHUNK_SIZE)]);
+    }
+
+    #[test]
+    fn test_chunk_key_order() {
+        let keys = [
+            chunk_key(1_000, 9),
+            chunk_key(1_000, 10),
+            chunk_key(1_001, 0),
+        ];
+        assert!(keys.iter().all(|key| key.starts_with(CHUNK_PREFIX)));
+        let mut sorted = keys.clone();
+        sorted.sort();
+        assert_eq!(sorted, keys);
+    }
+}
@@ -0,0 +1,137 @@
+use prost::Message;
+use std::fmt;
+use std::sync::Arc;
+
+use super::{model, Error, DEFAULT_DD_CONTENT_TYPE, MSGPACK_CONTENT_TYPE};
+use crate::dd_proto;
+
+/// Serializes the payloads of the exports, see
+/// [`DatadogPipelineBuilder::with_encoder`](super::DatadogPipelineBuilder::with_encoder).
+///
+/// [`ProtobufEncoder`] matches the agentless intake, [`MsgpackEncoder`] the `/v0.4/traces`
+/// endpoint of the agent. Custom encoders can target internal forwarders.
+pub trait Encoder: Send + Sync {
+    /// The `Content-Type` of the encoded payloads.
+    fn content_type(&self) -> &str;
+
+    /// Encode `payload`, holding the traces of a request along with the metadata of the
+    /// exporter.
+    ///
+    /// # Errors
+    ///
+    /// If `payload` can't be encoded, the export then fails with this error.
+    fn encode(&self, payload: &dd_proto::TracePayload) -> Result<Vec<u8>, Error>;
+}
+
+/// The payload in protobuf, as the agentless intake expects it.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct ProtobufEncoder;
+
+impl Encoder for ProtobufEncoder {
+    fn content_type(&self) -> &str {
+        DEFAULT_DD_CONTENT_TYPE
+    }
+
+    fn encode(&self, payload: &dd_proto::TracePayload) -> Result<Vec<u8>, Error> {
+        Ok(payload.encode_to_vec())
+    }
+}
+
+/// The trace chunks of the payload in msgpack, as the `/v0.4/traces` endpoint of the agent
+/// expects them. The payload metadata is left out.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct MsgpackEncoder;
+
+impl Encoder for MsgpackEncoder {
+    fn content_type(&self) -> &str {
+        MSGPACK_CONTENT_TYPE
+    }
+
+    fn encode(&self, payload: &dd_proto::TracePayload) -> Result<Vec<u8>, Error> {
+        let chunks: Vec<dd_proto::TraceChunk> = payload
+            .tracer_payloads
+            .iter()
+            .flat_map(|tracer| tracer.chunks.iter().cloned())
+            .collect();
+        model::v04::encode(&chunks)
+    }
+}
+
+/// The payload in JSON, e.g. for a debugging sink.
+#[cfg(feature = "debug-payload")]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct JsonEncoder;
+
+#[cfg(feature = "debug-payload")]
+impl Encoder for JsonEncoder {
+    fn content_type(&self) -> &str {
+        "application/json"
+    }
+
+    fn encode(&self, payload: &dd_proto::TracePayload) -> Result<Vec<u8>, Error> {
+        serde_json::to_vec(payload).map_err(|e| Error::Other(e.to_string()))
+    }
+}
+
+/// The encoder given to [`DatadogPipelineBuilder::with_encoder`](super::DatadogPipelineBuilder::with_encoder).
+#[derive(Clone)]
+pub(crate) struct EncoderHandle(pub(crate) Arc<dyn Encoder>);
+
+impl fmt::Debug for EncoderHandle {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("EncoderHandle")
+            .field(&self.0.content_type())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn payload() -> dd_proto::TracePayload {
+        dd_proto::TracePayload {
+            env: "production".to_string(),
+            tracer_payloads: vec![dd_proto::TracerPayload {
+                chunks: vec![dd_proto::TraceChunk {
+                    spans: vec![dd_proto::Span {
+                        service: "gateway".to_string(),
+                        name: "graphql".to_string(),
+                        trace_id: 1,
+                        span_id: 2,
+                        ..Default::default()
+                    }],
+                    ..Default::default()
+                }],
+                ..Default::default()
+            }],
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_protobuf_encoder() {
+        let encoded = ProtobufEncoder.encode(&payload()).unwrap();
+        assert_eq!(
+            dd_proto::TracePayload::decode(&encoded[..]).unwrap(),
+            payload()
+        );
+        assert_eq!(ProtobufEncoder.content_type(), "application/x-protobuf");
+    }
+
+    #[test]
+    fn test_msgpack_encoder() {
+        let encoded = MsgpackEncoder.encode(&payload()).unwrap();
+        let chunks = payload().tracer_payloads.remove(0).chunks;
+        assert_eq!(encoded, model::v04::encode(&chunks).unwrap());
+        assert_eq!(MsgpackEncoder.content_type(), "application/msgpack");
+    }
+
+    #[cfg(feature = "debug-payload")]
+    #[test]
+    fn test_json_encoder() {
+        let encoded = JsonEncoder.encode(&payload()).unwrap();
+        let json: serde_json::Value = serde_json::from_slice(&encoded).unwrap();
+        assert_eq!(json["env"], "production");
+    }
+}
@@ -0,0 +1,159 @@
+use std::fmt;
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::Duration;
+
+use super::Error;
+
+/// What happened to an export, see [`DatadogExporter::last_export_info`](super::DatadogExporter::last_export_info)
+/// and [`DatadogPipelineBuilder::with_export_info_handler`](super::DatadogPipelineBuilder::with_export_info_handler).
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ExportInfo {
+    /// Spans in the export.
+    pub span_count: usize,
+    /// Requests the spans were split into, up to the one which failed.
+    pub requests: usize,
+    /// The `X-Request-ID` header of each request, which its retries reuse, to find the requests
+    /// in the logs of the intake or of a proxy.
+    pub request_ids: Vec<String>,
+    /// Size of the encoded request bodies.
+    pub payload_bytes: usize,
+    /// Time spent sending the requests, retries included, until the last response.
+    pub latency: Duration,
+    /// Status of the last response, `None` when the intake couldn't be reached.
+    pub status: Option<u16>,
+    /// Whether the intake accepted every request.
+    pub success: bool,
+    /// Rate limit given by the last response, `None` when it had no rate-limit headers.
+    pub rate_limit: Option<RateLimit>,
+}
+
+/// Rate limit of the intake, from the `X-RateLimit-*` headers of its responses, to see when the
+/// exports get close to being throttled.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct RateLimit {
+    /// Requests allowed per period, from `X-RateLimit-Limit`.
+    pub limit: Option<u64>,
+    /// Requests left in the current period, from `X-RateLimit-Remaining`.
+    pub remaining: Option<u64>,
+    /// Time until the current period ends, from `X-RateLimit-Reset`.
+    pub reset: Option<Duration>,
+    /// Length of a period, from `X-RateLimit-Period`.
+    pub period: Option<Duration>,
+}
+
+impl RateLimit {
+    /// The rate limit given by the headers of a response, where `header` returns the value of a
+    /// header. `None` if none of the headers is there.
+    pub(crate) fn from_headers(header: impl Fn(&str) -> Option<String>) -> Option<Self> {
+        let number = |name: &str| header(name).and_then(|value| value.trim().parse::<u64>().ok());
+        let rate_limit = RateLimit {
+            limit: number("X-RateLimit-Limit"),
+            remaining: number("X-RateLimit-Remaining"),
+            reset: number("X-RateLimit-Reset").map(Duration::from_secs),
+            period: number("X-RateLimit-Period").map(Duration::from_secs),
+        };
+        (rate_limit != RateLimit::default()).then_some(rate_limit)
+    }
+}
+
+/// Callback given to [`DatadogPipelineBuilder::with_export_info_handler`](super::DatadogPipelineBuilder::with_export_info_handler).
+#[derive(Clone)]
+pub(crate) struct ExportInfoHandler(pub(crate) Arc<dyn Fn(&ExportInfo) + Send + Sync>);
+
+impl fmt::Debug for ExportInfoHandler {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("ExportInfoHandler")
+    }
+}
+
+/// Keeps the info of the last export and hands every one to the handler.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct ExportInfoRecorder {
+    last: Arc<Mutex<Option<ExportInfo>>>,
+    pub(crate) handler: Option<ExportInfoHandler>,
+}
+
+impl ExportInfoRecorder {
+    pub(crate) fn record(&self, info: ExportInfo) {
+        if let Some(ExportInfoHandler(handler)) = &self.handler {
+            handler(&info);
+        }
+        *self.last.lock().unwrap_or_else(PoisonError::into_inner) = Some(info);
+    }
+
+    pub(crate) fn last(&self) -> Option<ExportInfo> {
+        self.last
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clone()
+    }
+}
+
+/// The status of the response to a request, if there was one.
+pub(crate) fn response_status(result: &Result<u16, Error>) -> Option<u16> {
+    match result {
+        Ok(status) | Err(Error::ClientError { status, .. } | Error::ServerError { status, .. }) => {
+            Some(*status)
+        }
+        Err(_) => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_recorder() {
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let handler_seen = Arc::clone(&seen);
+        let recorder = ExportInfoRecorder {
+            handler: Some(ExportInfoHandler(Arc::new(move |info: &ExportInfo| {
+                handler_seen.lock().unwrap().push(info.span_count);
+            }))),
+            ..ExportInfoRecorder::default()
+        };
+        assert_eq!(recorder.last(), None);
+
+        let info = ExportInfo {
+            span_count: 3,
+            requests: 1,
+            payload_bytes: 120,
+            status: response_status(&Err(Error::from_response(503, String::new()))),
+            ..ExportInfo::default()
+        };
+        recorder.record(info.clone());
+
+        assert_eq!(recorder.last(), Some(info));
+        assert_eq!(recorder.last().unwrap().status, Some(503));
+        assert_eq!(*seen.lock().unwrap(), vec![3]);
+        assert_eq!(response_status(&Err(Error::Timeout)), None);
+    }
+
+    #[test]
+    fn test_rate_limit_from_headers() {
+        let headers = [
+            ("x-ratelimit-limit", "1000"),
+            ("x-ratelimit-remaining", " 12"),
+            ("x-ratelimit-reset", "30"),
+            ("x-ratelimit-period", "not a number"),
+        ];
+        let header = |name: &str| {
+            headers
+                .iter()
+                .find(|(header, _)| header.eq_ignore_ascii_case(name))
+                .map(|(_, value)| (*value).to_string())
+        };
+
+        assert_eq!(
+            RateLimit::from_headers(header),
+            Some(RateLimit {
+                limit: Some(1000),
+                remaining: Some(12),
+                reset: Some(Duration::from_secs(30)),
+                period: None,
+            })
+        );
+        assert_eq!(RateLimit::from_headers(|_| None), None);
+    }
+}
@@ -0,0 +1,214 @@
+use send_wrapper::SendWrapper;
+use serde::{Deserialize, Serialize};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use worker::kv::KvStore;
+
+use super::transport::{ExportRequest, Transport};
+use super::{send, time, AuthScheme, Error};
+
+/// Prefix of the keys of the persisted payloads.
+const KEY_PREFIX: &str = "dd-traces/";
+/// Shortest expiration accepted by Workers KV.
+const MIN_TTL: Duration = Duration::from_secs(60);
+
+/// Export requests which failed because of an intake outage, persisted in Workers KV so they
+/// outlive the invocation and are sent again by a later one.
+#[derive(Clone)]
+pub(crate) struct KvRetryBuffer {
+    kv: Arc<SendWrapper<KvStore>>,
+    max_payloads: usize,
+    ttl: Duration,
+    /// Whether the store may hold payloads, it's only listed when it does, or before the first
+    /// export since another isolate may have left some.
+    maybe_pending: Arc<AtomicBool>,
+    sequence: Arc<AtomicU64>,
+}
+
+impl std::fmt::Debug for KvRetryBuffer {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("KvRetryBuffer")
+            .field("max_payloads", &self.max_payloads)
+            .field("ttl", &self.ttl)
+            .finish_non_exhaustive()
+    }
+}
+
+/// What is persisted of a request besides its body, as the key metadata. The API key is not, it
+/// is added back when the request is sent again, possibly rotated.
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+struct StoredRequest {
+    url: String,
+    headers: Vec<(String, String)>,
+    authenticated: bool,
+}
+
+impl StoredRequest {
+    fn new(request: &ExportRequest, auth: &AuthScheme) -> Self {
+        let is_api_key = |name: &str| auth.is_auth_header(name);
+        StoredRequest {
+            url: request.url.clone(),
+            headers: request
+                .headers
+                .iter()
+                .filter(|(name, _)| !is_api_key(name))
+                .map(|(name, value)| (name.to_string(), value.clone()))
+                .collect(),
+            authenticated: request.headers.iter().any(|(name, _)| is_api_key(name)),
+        }
+    }
+
+    fn into_request(self, body: Vec<u8>, auth: &AuthScheme, api_key: &str) -> ExportRequest {
+        let request = self.headers.into_iter().fold(
+            ExportRequest::post(self.url, body),
+            |request, (name, value)| request.header(name, value),
+        );
+        if self.authenticated {
+            auth.authenticate(request, api_key)
+        } else {
+            request
+        }
+    }
+}
+
+impl KvRetryBuffer {
+    pub(crate) fn new(kv: KvStore, max_payloads: usize, ttl: Duration) -> Self {
+        KvRetryBuffer {
+            kv: Arc::new(SendWrapper::new(kv)),
+            max_payloads,
+            ttl: ttl.max(MIN_TTL),
+            maybe_pending: Arc::new(AtomicBool::new(true)),
+            sequence: Arc::new(AtomicU64::new(0)),
+        }
+    }
+
+    /// Persist `request`, authenticated as `auth` describes, unless `max_payloads` are already
+    /// waiting.
+    pub(crate) async fn persist(
+        &self,
+        request: &ExportRequest,
+        auth: &AuthScheme,
+    ) -> Result<(), Error> {
+        let waiting = self.list().await?;
+        if waiting.len() >= self.max_payloads {
+            return Err(Error::Other(format!(
+                "KV retry buffer is full, {} payloads are waiting",
+                waiting.len()
+            )));
+        }
+
+        let key = format!(
+            "{KEY_PREFIX}{:020}-{}",
+            time::to_unix_millis(time::now()),
+            self.sequence.fetch_add(1, Ordering::Relaxed)
+        );
+        self.kv
+            .put_bytes(&key, &request.body)
+            .and_then(|put| put.metadata(StoredRequest::new(request, auth)))
+            .map_err(kv_error)?
+            .expiration_ttl(self.ttl.as_secs())
+            .execute()
+            .await
+            .map_err(kv_error)?;
+
+        self.maybe_pending.store(true, Ordering::Relaxed);
+        Ok(())
+    }
+
+    /// Send the persisted requests again, oldest first, deleting the ones which went through or
+    /// were rejected. Stops at the first retryable failure, the intake is still unavailable.
+    pub(crate) async fn drain(
+        &self,
+        transport: &Transport,
+        auth: &AuthScheme,
+        api_key: &str,
+        timeout: Option<Duration>,
+    ) -> Result<(), Error> {
+        if !self.maybe_pending.swap(false, Ordering::Relaxed) {
+            return Ok(());
+        }
+
+        for key in self.list().await? {
+            let (body, stored) = self
+                .kv
+                .get(&key)
+                .bytes_with_metadata::<StoredRequest>()
+                .await
+                .map_err(kv_error)?;
+            if let (Some(body), Some(stored)) = (body, stored) {
+                match send(transport, stored.into_request(body, auth, api_key), timeout)
+                    .await
+                    .and_then(|response| response.check())
+                {
+                    Err(err) if err.is_retryable() => {
+                        self.maybe_pending.store(true, Ordering::Relaxed);
+                        return Err(err);
+                    }
+                    Ok(_) | Err(_) => {}
+                }
+            }
+            self.kv.delete(&key).await.map_err(kv_error)?;
+        }
+
+        Ok(())
+    }
+
+    /// Keys of the persisted requests, oldest first.
+    async fn list(&self) -> Result<Vec<String>, Error> {
+        let response = self
+            .kv
+            .list()
+            .prefix(KEY_PREFIX.to_string())
+            .limit(u64::try_from(self.max_payloads).unwrap_or(u64::MAX))
+            .execute()
+            .await
+            .map_err(kv_error)?;
+
+        Ok(response.keys.into_iter().map(|key| key.name).collect())
+    }
+}
+
+#[allow(clippy::needless_pass_by_value)]
+fn kv_error(err: worker::kv::KvError) -> Error {
+    Error::Other(format!("Workers KV error: {err:?}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::exporter::{CONTENT_TYPE_HEADER, DEFAULT_DD_API_KEY_HEADER};
+
+    #[test]
+    fn test_stored_request_round_trip() {
+        let request =
+            ExportRequest::post("https://example.com/api/v0.2/traces".to_string(), vec![1])
+                .header(CONTENT_TYPE_HEADER, "application/x-protobuf")
+                .header(DEFAULT_DD_API_KEY_HEADER, "old-key");
+
+        let stored = StoredRequest::new(&request, &AuthScheme::default());
+        assert_eq!(
+            stored.headers,
+            vec![(
+                CONTENT_TYPE_HEADER.to_string(),
+                "application/x-protobuf".to_string()
+            )]
+        );
+        assert!(stored.authenticated);
+
+        let request = stored.into_request(vec![1], &AuthScheme::default(), "new-key");
+        assert_eq!(request.url, "https://example.com/api/v0.2/traces");
+        assert_eq!(&request.body[..], &[1]);
+        assert_eq!(
+            request
+                .headers
+                .iter()
+                .map(|(name, value)| (&**name, value.as_str()))
+                .collect::<Vec<_>>(),
+            vec![
+                (CONTENT_TYPE_HEADER, "application/x-protobuf"),
+                (DEFAULT_DD_API_KEY_HEADER, "new-key"),
+            ]
+        );
+    }
+}
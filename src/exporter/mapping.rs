@@ -0,0 +1,1667 @@
+use opentelemetry::sdk::export::trace::SpanData;
+use opentelemetry::sdk::trace::EvictedHashMap;
+use opentelemetry::trace::{Event, Link, SpanContext, SpanId, SpanKind, StatusCode};
+use opentelemetry::{Key, Value};
+use regex::Regex;
+use std::borrow::Cow;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fmt;
+use std::sync::Arc;
+use std::time::SystemTime;
+
+use super::arena::assign;
+use super::obfuscate::obfuscate_sql;
+use super::quantize::quantize_url;
+use super::redact::{Redaction, REDACTED};
+use super::Error;
+use crate::dd_proto;
+use crate::propagator::{
+    sampling_priority_from_trace_state, SamplingPriority, TRACE_FLAG_DEFERRED,
+};
+
+/// Metric of the local root spans holding the sampling priority of their trace.
+const SAMPLING_PRIORITY_METRIC: &str = "_sampling_priority_v1";
+/// Attributes of the dd-trace clients forcing the sampling decision of their trace.
+const MANUAL_KEEP_ATTRIBUTE: &str = "manual.keep";
+const MANUAL_DROP_ATTRIBUTE: &str = "manual.drop";
+/// Attribute of the dd-trace clients keeping the trace when positive, dropping it otherwise.
+const SAMPLING_PRIORITY_ATTRIBUTE: &str = "sampling.priority";
+/// Metric computing trace metrics for a span which isn't a service entry span.
+const MEASURED_METRIC: &str = "_dd.measured";
+/// Attributes opting a span into trace metrics.
+const MEASURED_ATTRIBUTES: &[&str] = &["datadog.measured", MEASURED_METRIC];
+/// Metric of the spans sampled into App Analytics, the legacy Trace Search, at this rate.
+const ANALYTICS_SAMPLE_RATE_METRIC: &str = "_dd1.sr.eausr";
+/// Name of the events recording an exception, see the `OpenTelemetry` semantic conventions.
+const EXCEPTION_EVENT: &str = "exception";
+/// Meta keys of the error of a span, and the attributes of the exception events they come from.
+const ERROR_META: &[(&str, &str)] = &[
+    ("error.msg", "exception.message"),
+    ("error.type", "exception.type"),
+    ("error.stack", "exception.stacktrace"),
+];
+/// Datadog tags of the HTTP attributes named differently by the `OpenTelemetry` semantic
+/// conventions.
+const HTTP_TAGS: &[(&str, &str)] = &[
+    ("http.request.method", HTTP_METHOD_TAG),
+    ("http.response.status_code", HTTP_STATUS_CODE_TAG),
+    ("url.full", HTTP_URL_TAG),
+];
+const HTTP_METHOD_TAG: &str = "http.method";
+const HTTP_URL_TAG: &str = "http.url";
+const HTTP_ROUTE_TAG: &str = "http.route";
+/// Kept in the meta, as a string, where Datadog expects it.
+const HTTP_STATUS_CODE_TAG: &str = "http.status_code";
+/// Datadog serverless tags of the `faas.*` and `cloud.*` attributes, lining up the Workers traces
+/// with the serverless views.
+const SERVERLESS_TAGS: &[(&str, &str)] = &[
+    ("faas.trigger", "function_trigger.event_source"),
+    ("faas.invocation_id", "request_id"),
+    ("faas.execution", "request_id"),
+    ("faas.name", "functionname"),
+    ("faas.version", "function_version"),
+    ("faas.coldstart", "cold_start"),
+    ("cloud.provider", "cloud_provider"),
+    ("cloud.region", "region"),
+    ("cloud.availability_zone", "datacenter"),
+    ("cloud.account.id", "account_id"),
+];
+/// Datadog tags of the GraphQL attributes named differently by the `OpenTelemetry` semantic
+/// conventions.
+const GRAPHQL_TAGS: &[(&str, &str)] = &[("graphql.document", "graphql.source")];
+const GRAPHQL_OPERATION_NAME_TAG: &str = "graphql.operation.name";
+const GRAPHQL_OPERATION_TYPE_TAG: &str = "graphql.operation.type";
+/// Statement of the database spans, obfuscated in the tag and the resource derived from it.
+const DB_STATEMENT_TAG: &str = "db.statement";
+/// Meta key of the events of a span, serialized as JSON as the dd-trace clients do.
+const EVENTS_META: &str = "events";
+/// Meta key of the links of a span, serialized as JSON for the Datadog backend.
+const SPAN_LINKS_META: &str = "_dd.span_links";
+/// Flag set in the `flags` of a span link when its trace flags are known.
+const SPAN_LINK_FLAGS_SET: u32 = 1 << 31;
+/// Meta of the local roots holding the upper 64 bits of a 128-bit trace id, in hexadecimal.
+const TRACE_ID_HIGH_META: &str = "_dd.p.tid";
+/// Metric of the spans computing the trace metrics of their service.
+const TOP_LEVEL_METRIC: &str = "_dd.top_level";
+/// Attribute of the dd-trace clients flagging a span as an analytics event, `true` for a sample
+/// rate of 1 or the sample rate itself.
+const ANALYTICS_EVENT_ATTRIBUTE: &str = "analytics.event";
+
+/// Callback given to [`DatadogPipelineBuilder::with_name_mapping`](super::DatadogPipelineBuilder::with_name_mapping).
+#[derive(Clone)]
+pub(crate) struct NameMapping(
+    pub(crate) Arc<dyn for<'a> Fn(&'a SpanData) -> Cow<'a, str> + Send + Sync>,
+);
+
+impl fmt::Debug for NameMapping {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("NameMapping")
+    }
+}
+
+/// Callback given to [`DatadogPipelineBuilder::with_service_name_mapping`](super::DatadogPipelineBuilder::with_service_name_mapping).
+#[derive(Clone)]
+pub(crate) struct ServiceMapping(
+    pub(crate) Arc<dyn for<'a> Fn(&'a SpanData) -> Cow<'a, str> + Send + Sync>,
+);
+
+impl fmt::Debug for ServiceMapping {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("ServiceMapping")
+    }
+}
+
+/// Longest name, service and type of a span accepted by Datadog, in characters.
+const MAX_NAME_LEN: usize = 100;
+/// Longest resource of a span accepted by Datadog, in characters.
+const MAX_RESOURCE_LEN: usize = 5000;
+/// Longest meta value of a span kept, in characters.
+const MAX_META_VALUE_LEN: usize = 5000;
+/// Most meta and metrics entries of a span kept, each.
+const MAX_TAGS_PER_SPAN: usize = 1024;
+/// Longest tag key kept, in characters.
+const MAX_TAG_KEY_LEN: usize = 200;
+/// Metric of the spans truncated to fit the limits of Datadog.
+const TRUNCATED_METRIC: &str = "_dd.truncated";
+/// Tags set by the exporter, kept when the tags of a span are capped.
+const RESERVED_TAGS: &[&str] = &["service", "env", "version", "span.kind"];
+
+/// Resource attributes merged into the tags of the spans, set by
+/// [`DatadogPipelineBuilder::with_resource_attribute_allowlist`](super::DatadogPipelineBuilder::with_resource_attribute_allowlist)
+/// and [`DatadogPipelineBuilder::with_resource_attribute_denylist`](super::DatadogPipelineBuilder::with_resource_attribute_denylist).
+#[derive(Clone, Debug, Default)]
+pub(crate) struct ResourceFilter {
+    /// Only these attributes are merged when set.
+    pub(crate) allow: Option<HashSet<String>>,
+    pub(crate) deny: HashSet<String>,
+}
+
+impl ResourceFilter {
+    /// Whether the resource attribute `key` is merged, `service.name` never is as it's the
+    /// service of the spans.
+    pub(crate) fn allows(&self, key: &str) -> bool {
+        key != "service.name"
+            && !self.deny.contains(key)
+            && self
+                .allow
+                .as_ref()
+                .map_or(true, |allow| allow.contains(key))
+    }
+}
+
+/// Resources of the traces which aren't exported, set by
+/// [`DatadogPipelineBuilder::with_ignore_resources`](super::DatadogPipelineBuilder::with_ignore_resources).
+#[derive(Clone, Debug, Default)]
+pub(crate) struct IgnoredResources {
+    patterns: Vec<Regex>,
+}
+
+impl IgnoredResources {
+    /// Ignore the traces whose root resource matches one of `patterns`.
+    pub(crate) fn new(patterns: &[String]) -> Result<Self, Error> {
+        let patterns = patterns
+            .iter()
+            .map(|pattern| {
+                Regex::new(pattern)
+                    .map_err(|e| Error::Other(format!("invalid ignored resource {pattern}: {e}")))
+            })
+            .collect::<Result<_, _>>()?;
+        Ok(IgnoredResources { patterns })
+    }
+
+    /// Whether the resource of a local root of the trace chunk matches, as the
+    /// `ignore_resources` option of the Datadog Agent.
+    pub(crate) fn ignores(&self, spans: &mut [dd_proto::Span]) -> bool {
+        !self.patterns.is_empty()
+            && local_roots(spans).any(|span| {
+                self.patterns
+                    .iter()
+                    .any(|pattern| pattern.is_match(&span.resource))
+            })
+    }
+}
+
+/// `db.system` values of SQL databases, whose spans have the `sql` type.
+const SQL_SYSTEMS: &[&str] = &[
+    "postgresql",
+    "mysql",
+    "mariadb",
+    "mssql",
+    "oracle",
+    "sqlite",
+    "db2",
+    "cockroachdb",
+    "other_sql",
+];
+
+/// The Datadog type of a span of `kind`, where `attribute` returns the value of an attribute:
+/// the `span.type` attribute, or a type inferred from the kind and the semantic conventions.
+pub(crate) fn span_type<'a>(
+    kind: &SpanKind,
+    attribute: impl Fn(&'static str) -> Option<Cow<'a, str>>,
+) -> Cow<'a, str> {
+    if let Some(span_type) = attribute("span.type") {
+        return span_type;
+    }
+
+    let span_type = if let Some(system) = attribute("db.system") {
+        if SQL_SYSTEMS.contains(&system.as_ref()) {
+            "sql"
+        } else {
+            "db"
+        }
+    } else if attribute("messaging.system").is_some() {
+        "queue"
+    } else {
+        match kind {
+            SpanKind::Server => "web",
+            SpanKind::Client
+                if attribute("http.method").is_some()
+                    || attribute("http.request.method").is_some() =>
+            {
+                "http"
+            }
+            SpanKind::Producer | SpanKind::Consumer => "queue",
+            SpanKind::Client | SpanKind::Internal => "custom",
+        }
+    };
+    Cow::Borrowed(span_type)
+}
+
+/// The operation name Datadog gives to a span of `kind` ingested through OpenTelemetry, where
+/// `attribute` returns the value of an attribute: the `operation.name` attribute, or a name
+/// derived from the kind and the semantic conventions, e.g. `http.server.request` or
+/// `postgresql.query`.
+pub(crate) fn operation_name<'a>(
+    kind: &SpanKind,
+    attribute: impl Fn(&'static str) -> Option<Cow<'a, str>>,
+) -> Cow<'a, str> {
+    if let Some(name) = attribute("operation.name") {
+        return name;
+    }
+
+    let is_client = matches!(kind, SpanKind::Client);
+    let is_server = matches!(kind, SpanKind::Server);
+    let side = if is_server { "server" } else { "client" };
+
+    if attribute("http.request.method").is_some() || attribute("http.method").is_some() {
+        if is_server {
+            return Cow::Borrowed("http.server.request");
+        }
+        if is_client {
+            return Cow::Borrowed("http.client.request");
+        }
+    }
+    if let (Some(system), true) = (attribute("db.system"), is_client) {
+        return Cow::Owned(format!("{}.query", system.to_lowercase()));
+    }
+    if let (Some(system), Some(operation), false) = (
+        attribute("messaging.system"),
+        attribute("messaging.operation"),
+        matches!(kind, SpanKind::Internal),
+    ) {
+        return Cow::Owned(format!("{system}.{operation}").to_lowercase());
+    }
+    if let Some(system) = attribute("rpc.system") {
+        if system == "aws-api" && is_client {
+            return match attribute("rpc.service") {
+                Some(service) => Cow::Owned(format!("aws.{}.request", service.to_lowercase())),
+                None => Cow::Borrowed("aws.client.request"),
+            };
+        }
+        if is_client || is_server {
+            return Cow::Owned(format!("{}.{side}.request", system.to_lowercase()));
+        }
+    }
+    if let (Some(provider), Some(name), true) = (
+        attribute("faas.invoked_provider"),
+        attribute("faas.invoked_name"),
+        is_client,
+    ) {
+        return Cow::Owned(format!("{provider}.{name}.invoke").to_lowercase());
+    }
+    if let (Some(trigger), true) = (attribute("faas.trigger"), is_server) {
+        return Cow::Owned(format!("{}.invoke", trigger.to_lowercase()));
+    }
+    if attribute(GRAPHQL_OPERATION_TYPE_TAG).is_some() {
+        return Cow::Borrowed("graphql.server.request");
+    }
+    if let (Some(protocol), true) = (attribute("network.protocol.name"), is_client || is_server) {
+        return Cow::Owned(format!("{}.{side}.request", protocol.to_lowercase()));
+    }
+
+    match kind {
+        SpanKind::Server => Cow::Borrowed("server.request"),
+        SpanKind::Client => Cow::Borrowed("client.request"),
+        kind => Cow::Borrowed(span_kind(kind)),
+    }
+}
+
+/// The `span.kind` tag of a span of `kind`, which Datadog uses to infer the services it calls.
+pub(crate) fn span_kind(kind: &SpanKind) -> &'static str {
+    match kind {
+        SpanKind::Server => "server",
+        SpanKind::Client => "client",
+        SpanKind::Producer => "producer",
+        SpanKind::Consumer => "consumer",
+        SpanKind::Internal => "internal",
+    }
+}
+
+/// Add an attribute to `span`: numbers to its metrics, which Datadog can aggregate as measures,
+/// and the other values to its meta. A truthy `datadog.measured` or `_dd.measured` attribute sets
+/// the `_dd.measured` metric instead, and the HTTP, serverless and GraphQL attributes get their
+/// Datadog tag names. The literals of a `db.statement` are obfuscated, and it names the resource
+/// when there isn't one. The keys of the other attributes are normalized, see
+/// [`normalize_tag_key`].
+#[allow(clippy::cast_precision_loss)]
+pub(crate) fn add_attribute(span: &mut dd_proto::Span, key: Key, value: Value) {
+    let key = match HTTP_TAGS
+        .iter()
+        .chain(SERVERLESS_TAGS)
+        .chain(GRAPHQL_TAGS)
+        .find(|(name, _)| *name == key.as_str())
+    {
+        Some((_, tag)) => Key::from_static_str(tag),
+        None => key,
+    };
+    if key.as_str() == HTTP_STATUS_CODE_TAG {
+        span.meta.insert(key.to_string(), value.to_string());
+        return;
+    }
+    if key.as_str() == DB_STATEMENT_TAG {
+        let statement = obfuscate_sql(&value.as_str());
+        if span.resource.is_empty() {
+            assign(&mut span.resource, &statement);
+        }
+        span.meta.insert(key.to_string(), statement);
+        return;
+    }
+    if MEASURED_ATTRIBUTES.contains(&key.as_str()) {
+        if is_truthy(&value) {
+            span.metrics.insert(MEASURED_METRIC.to_string(), 1.0);
+        }
+        return;
+    }
+    if [
+        MANUAL_KEEP_ATTRIBUTE,
+        MANUAL_DROP_ATTRIBUTE,
+        SAMPLING_PRIORITY_ATTRIBUTE,
+    ]
+    .contains(&key.as_str())
+    {
+        // Applied to the whole trace, see `manual_sampling_priority`.
+        return;
+    }
+    if key.as_str() == ANALYTICS_EVENT_ATTRIBUTE {
+        if let Some(rate) = analytics_sample_rate(&value) {
+            span.metrics
+                .insert(ANALYTICS_SAMPLE_RATE_METRIC.to_string(), rate);
+        }
+        return;
+    }
+
+    let key = normalize_tag_key(key.as_str());
+    if key.is_empty() {
+        return;
+    }
+    match value {
+        Value::I64(number) => {
+            span.metrics.insert(key.into_owned(), number as f64);
+        }
+        Value::F64(number) => {
+            span.metrics.insert(key.into_owned(), number);
+        }
+        value => {
+            span.meta.insert(key.into_owned(), value.to_string());
+        }
+    }
+}
+
+/// `key` normalized as the Datadog Agent does, so it can be indexed: lowercased, starting with a
+/// letter, other characters than letters, digits, `_`, `:`, `.`, `/` and `-` replaced by `_`,
+/// without repeated or trailing `_`, and at most 200 characters long.
+pub(crate) fn normalize_tag_key(key: &str) -> Cow<'_, str> {
+    let normalized = key.len() <= MAX_TAG_KEY_LEN
+        && key.starts_with(|c: char| c.is_ascii_lowercase())
+        && !key.ends_with('_')
+        && !key.contains("__")
+        && key.bytes().all(|byte| {
+            byte.is_ascii_lowercase()
+                || byte.is_ascii_digit()
+                || is_tag_punctuation(char::from(byte))
+        });
+    if normalized {
+        return Cow::Borrowed(key);
+    }
+
+    let mut normalized = String::with_capacity(key.len());
+    for c in key.chars().flat_map(char::to_lowercase) {
+        if normalized.is_empty() && !c.is_alphabetic() {
+            continue;
+        }
+        let c = if c.is_alphanumeric() || is_tag_punctuation(c) {
+            c
+        } else {
+            '_'
+        };
+        if c == '_' && normalized.ends_with('_') {
+            continue;
+        }
+        normalized.push(c);
+    }
+    truncate(&mut normalized, MAX_TAG_KEY_LEN);
+    let len = normalized.trim_end_matches('_').len();
+    normalized.truncate(len);
+    Cow::Owned(normalized)
+}
+
+fn is_tag_punctuation(c: char) -> bool {
+    matches!(c, '_' | ':' | '.' | '/' | '-')
+}
+
+/// Complete `span` from its HTTP tags: the server spans answering with a 5xx status are errors,
+/// and a span without a resource gets `"METHOD /route"`, grouping its requests by route, or else
+/// `"METHOD /path"` with the quantized path of its URL.
+pub(crate) fn set_http_conventions(span: &mut dd_proto::Span, kind: &SpanKind) {
+    let status = span
+        .meta
+        .get(HTTP_STATUS_CODE_TAG)
+        .and_then(|status| status.trim().parse::<u16>().ok());
+    if matches!(kind, SpanKind::Server) && matches!(status, Some(500..=599)) {
+        span.error = 1;
+    }
+
+    if span.resource.is_empty() {
+        if let Some(method) = span.meta.get(HTTP_METHOD_TAG) {
+            if let Some(route) = span.meta.get(HTTP_ROUTE_TAG) {
+                span.resource = format!("{method} {route}");
+            } else if let Some(url) = span.meta.get(HTTP_URL_TAG) {
+                span.resource = format!("{method} {}", quantize_url(url));
+            }
+        }
+    }
+}
+
+/// Name the resource of a GraphQL server span after its operation, e.g. `query GetUser`, instead
+/// of the route all the operations are sent to.
+pub(crate) fn set_graphql_conventions(span: &mut dd_proto::Span, kind: &SpanKind) {
+    if !matches!(kind, SpanKind::Server) {
+        return;
+    }
+    if let Some(name) = span.meta.get(GRAPHQL_OPERATION_NAME_TAG) {
+        span.resource = match span.meta.get(GRAPHQL_OPERATION_TYPE_TAG) {
+            Some(operation_type) => format!("{operation_type} {name}"),
+            None => name.clone(),
+        };
+    }
+}
+
+/// Tag `span` with the unified service tags `service`, `env` and `version`, which the deployment
+/// tracking and version comparison read from the spans. An `env` or `version` attribute wins.
+pub(crate) fn set_unified_service_tags(span: &mut dd_proto::Span, env: &str, version: &str) {
+    span.meta
+        .insert("service".to_string(), span.service.clone());
+    for (tag, value) in [("env", env), ("version", version)] {
+        if !value.is_empty() {
+            span.meta
+                .entry(tag.to_string())
+                .or_insert_with(|| value.to_string());
+        }
+    }
+}
+
+/// Enforce the limits of Datadog on `span`, which otherwise rejects the whole payload: truncate
+/// its name, service, type, resource and meta values, and drop the tags past the most a span can
+/// have, setting the `_dd.truncated` metric if anything was cut.
+pub(crate) fn enforce_limits(span: &mut dd_proto::Span) {
+    let mut truncated = false;
+    for name in [&mut span.name, &mut span.service, &mut span.r#type] {
+        truncated |= truncate(name, MAX_NAME_LEN);
+    }
+    truncated |= truncate(&mut span.resource, MAX_RESOURCE_LEN);
+    for value in span.meta.values_mut() {
+        truncated |= truncate(value, MAX_META_VALUE_LEN);
+    }
+
+    // The last tags in key order are dropped, but never those of the exporter or of Datadog.
+    let reserved = |key: &str| key.starts_with('_') || RESERVED_TAGS.contains(&key);
+    let excess = span.meta.len().saturating_sub(MAX_TAGS_PER_SPAN);
+    let dropped: Vec<String> = span
+        .meta
+        .keys()
+        .rev()
+        .filter(|key| !reserved(key.as_str()) && !key.starts_with("error."))
+        .take(excess)
+        .cloned()
+        .collect();
+    let excess = span.metrics.len().saturating_sub(MAX_TAGS_PER_SPAN);
+    let dropped_metrics: Vec<String> = span
+        .metrics
+        .keys()
+        .rev()
+        .filter(|key| !reserved(key.as_str()))
+        .take(excess)
+        .cloned()
+        .collect();
+    truncated |= !dropped.is_empty() || !dropped_metrics.is_empty();
+    for key in dropped {
+        span.meta.remove(&key);
+    }
+    for key in dropped_metrics {
+        span.metrics.remove(&key);
+    }
+
+    if truncated {
+        span.metrics.insert(TRUNCATED_METRIC.to_string(), 1.0);
+    }
+}
+
+/// Truncate `value` to `max` characters, returns whether it was longer.
+fn truncate(value: &mut String, max: usize) -> bool {
+    match value.char_indices().nth(max) {
+        Some((end, _)) => {
+            value.truncate(end);
+            true
+        }
+        None => false,
+    }
+}
+
+/// Tag a trace chunk with `trace_tags`, the `root_span_tags` of its root span and the `_dd.p.*`
+/// tags propagated with the trace, which win as they carry decisions made upstream.
+pub(crate) fn set_chunk_tags(
+    chunk: &mut dd_proto::TraceChunk,
+    trace_tags: &BTreeMap<String, String>,
+    root_span_tags: &[String],
+    propagated_tags: Vec<(String, String)>,
+) {
+    chunk.tags.extend(
+        trace_tags
+            .iter()
+            .map(|(key, value)| (key.clone(), value.clone())),
+    );
+    let root = chunk
+        .spans
+        .iter()
+        .find(|span| span.parent_id == 0)
+        .or_else(|| chunk.spans.first());
+    if let Some(root) = root {
+        for key in root_span_tags {
+            if let Some(value) = root.meta.get(key) {
+                chunk.tags.insert(key.clone(), value.clone());
+            }
+        }
+    }
+    chunk.tags.extend(propagated_tags);
+}
+
+/// Set the error of `span` from its status and its last `exception` event: `error.msg`,
+/// `error.type` and `error.stack` come from the attributes of the event, or the message from the
+/// description of an error status, so Datadog shows more than a failed span. The redacted
+/// attributes of the event are set to `[REDACTED]`.
+pub(crate) fn set_error<'a>(
+    span: &mut dd_proto::Span,
+    status_code: &StatusCode,
+    status_message: &str,
+    events: impl IntoIterator<Item = &'a Event>,
+    redaction: &Redaction,
+) {
+    let exception = events
+        .into_iter()
+        .filter(|event| event.name == EXCEPTION_EVENT)
+        .last();
+    if let Some(exception) = exception {
+        for (meta, key) in ERROR_META {
+            if let Some(attribute) = exception
+                .attributes
+                .iter()
+                .find(|attribute| attribute.key.as_str() == *key)
+            {
+                let value = if redaction.redacts(key) {
+                    REDACTED.to_string()
+                } else {
+                    attribute.value.to_string()
+                };
+                span.meta.insert((*meta).to_string(), value);
+            }
+        }
+    }
+
+    let failed = matches!(status_code, StatusCode::Error);
+    if failed && !status_message.is_empty() {
+        span.meta
+            .entry("error.msg".to_string())
+            .or_insert_with(|| status_message.to_string());
+    }
+    span.error = i32::from(failed || exception.is_some());
+}
+
+/// Set the `events` meta of `span` to its events as a JSON array of objects with their
+/// `name`, `time_unix_nano` and `attributes`, keeping the milestones recorded in the span. The
+/// redacted attributes are set to `[REDACTED]`.
+pub(crate) fn set_events<'a>(
+    span: &mut dd_proto::Span,
+    events: impl IntoIterator<Item = &'a Event>,
+    redaction: &Redaction,
+) {
+    let events: Vec<serde_json::Value> = events
+        .into_iter()
+        .map(|event| {
+            let time_unix_nano = event
+                .timestamp
+                .duration_since(SystemTime::UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_nanos();
+            let attributes: serde_json::Map<String, serde_json::Value> = event
+                .attributes
+                .iter()
+                .map(|attribute| {
+                    let value = if redaction.redacts(attribute.key.as_str()) {
+                        REDACTED.into()
+                    } else {
+                        json_value(&attribute.value)
+                    };
+                    (attribute.key.to_string(), value)
+                })
+                .collect();
+            serde_json::json!({
+                "name": event.name,
+                "time_unix_nano": u64::try_from(time_unix_nano).unwrap_or(u64::MAX),
+                "attributes": attributes,
+            })
+        })
+        .collect();
+    if !events.is_empty() {
+        span.meta.insert(
+            EVENTS_META.to_string(),
+            serde_json::Value::Array(events).to_string(),
+        );
+    }
+}
+
+/// Set the `_dd.span_links` meta of `span` to its links as a JSON array of objects with their
+/// hex `trace_id` and `span_id`, their string `attributes`, `tracestate` and `flags`. The
+/// redacted attributes are set to `[REDACTED]`.
+pub(crate) fn set_span_links<'a>(
+    span: &mut dd_proto::Span,
+    links: impl IntoIterator<Item = &'a Link>,
+    redaction: &Redaction,
+) {
+    let links: Vec<serde_json::Value> = links
+        .into_iter()
+        .map(|link| {
+            let span_context = link.span_context();
+            let mut json = serde_json::Map::new();
+            json.insert(
+                "trace_id".to_string(),
+                format!(
+                    "{:032x}",
+                    u128::from_be_bytes(span_context.trace_id().to_bytes())
+                )
+                .into(),
+            );
+            json.insert(
+                "span_id".to_string(),
+                format!(
+                    "{:016x}",
+                    u64::from_be_bytes(span_context.span_id().to_bytes())
+                )
+                .into(),
+            );
+            if !link.attributes().is_empty() {
+                let attributes: serde_json::Map<String, serde_json::Value> = link
+                    .attributes()
+                    .iter()
+                    .map(|attribute| {
+                        let value = if redaction.redacts(attribute.key.as_str()) {
+                            REDACTED.to_string()
+                        } else {
+                            attribute.value.to_string()
+                        };
+                        (attribute.key.to_string(), value.into())
+                    })
+                    .collect();
+                json.insert("attributes".to_string(), attributes.into());
+            }
+            let trace_state = span_context.trace_state().header();
+            if !trace_state.is_empty() {
+                json.insert("tracestate".to_string(), trace_state.into());
+            }
+            json.insert(
+                "flags".to_string(),
+                (u32::from(span_context.trace_flags().to_u8()) | SPAN_LINK_FLAGS_SET).into(),
+            );
+            serde_json::Value::Object(json)
+        })
+        .collect();
+    if !links.is_empty() {
+        span.meta.insert(
+            SPAN_LINKS_META.to_string(),
+            serde_json::Value::Array(links).to_string(),
+        );
+    }
+}
+
+/// `value` as JSON, arrays as their string representation.
+fn json_value(value: &Value) -> serde_json::Value {
+    match value {
+        Value::Bool(value) => serde_json::Value::Bool(*value),
+        Value::I64(value) => serde_json::Value::from(*value),
+        Value::F64(value) => serde_json::Value::from(*value),
+        value => serde_json::Value::String(value.as_str().into_owned()),
+    }
+}
+
+/// Whether `value` is `true`, a non zero number or a string such as `"true"` or `"1"`.
+fn is_truthy(value: &Value) -> bool {
+    match value {
+        Value::Bool(value) => *value,
+        Value::I64(value) => *value != 0,
+        Value::F64(value) => *value != 0.0,
+        value => matches!(value.as_str().trim(), "true" | "True" | "TRUE" | "1"),
+    }
+}
+
+/// The analytics sample rate given by an `analytics.event` attribute, clamped to `[0, 1]`.
+#[allow(clippy::cast_precision_loss)]
+fn analytics_sample_rate(value: &Value) -> Option<f64> {
+    let rate = match value {
+        Value::Bool(enabled) => f64::from(u8::from(*enabled)),
+        Value::I64(rate) => *rate as f64,
+        Value::F64(rate) => *rate,
+        value => match value.as_str().trim() {
+            "true" | "True" | "TRUE" => 1.0,
+            "false" | "False" | "FALSE" => 0.0,
+            rate => rate.parse().ok()?,
+        },
+    };
+    (!rate.is_nan()).then(|| rate.clamp(0.0, 1.0))
+}
+
+/// The sampling priority of the trace of `span_context`: the manual one recorded in its trace
+/// state, or the decision of the sampler from its trace flags. `None` when the decision was
+/// deferred by the caller and nothing kept the trace since.
+pub(crate) fn sampling_priority(span_context: &SpanContext) -> Option<SamplingPriority> {
+    let manual = sampling_priority_from_trace_state(span_context.trace_state())
+        .filter(|priority| priority.is_manual());
+    let trace_flags = span_context.trace_flags();
+    match manual {
+        Some(priority) => Some(priority),
+        None if span_context.is_sampled() => Some(SamplingPriority::AutoKeep),
+        None if trace_flags & TRACE_FLAG_DEFERRED == TRACE_FLAG_DEFERRED => None,
+        None => Some(SamplingPriority::AutoReject),
+    }
+}
+
+/// The sampling decision forced by the application through the `manual.keep`, `manual.drop` or
+/// `sampling.priority` attributes of a span, as the dd-trace clients let it.
+#[allow(clippy::cast_possible_truncation)]
+pub(crate) fn manual_sampling_priority(attributes: &EvictedHashMap) -> Option<SamplingPriority> {
+    let attribute = |key| attributes.get(&Key::from_static_str(key));
+
+    if attribute(MANUAL_KEEP_ATTRIBUTE).map_or(false, is_truthy) {
+        return Some(SamplingPriority::UserKeep);
+    }
+    if attribute(MANUAL_DROP_ATTRIBUTE).map_or(false, is_truthy) {
+        return Some(SamplingPriority::UserReject);
+    }
+    let priority = match attribute(SAMPLING_PRIORITY_ATTRIBUTE)? {
+        Value::I64(priority) => *priority,
+        Value::F64(priority) => *priority as i64,
+        value => value.as_str().trim().parse().ok()?,
+    };
+    Some(if priority > 0 {
+        SamplingPriority::UserKeep
+    } else {
+        SamplingPriority::UserReject
+    })
+}
+
+/// The sampling decision forced on `span`, by its attributes or by
+/// [`with_sampling_priority`](crate::propagator::with_sampling_priority) before it started.
+pub(crate) fn manual_span_sampling_priority(span: &SpanData) -> Option<SamplingPriority> {
+    let traced = sampling_priority_from_trace_state(span.span_context.trace_state())
+        .filter(|priority| priority.is_manual());
+    manual_sampling_priority(&span.attributes)
+        .into_iter()
+        .chain(traced)
+        .max_by_key(|priority| *priority as i32)
+}
+
+/// The span of `trace` whose parent isn't in it, or its first span.
+pub(crate) fn local_root(trace: &[SpanData]) -> Option<&SpanData> {
+    trace
+        .iter()
+        .find(|span| {
+            span.parent_span_id == SpanId::INVALID
+                || !trace
+                    .iter()
+                    .any(|parent| parent.span_context.span_id() == span.parent_span_id)
+        })
+        .or_else(|| trace.first())
+}
+
+/// The spans of a trace chunk whose parent isn't in the chunk.
+pub(crate) fn local_roots(
+    spans: &mut [dd_proto::Span],
+) -> impl Iterator<Item = &mut dd_proto::Span> {
+    let span_ids: HashSet<u64> = spans.iter().map(|span| span.span_id).collect();
+    spans
+        .iter_mut()
+        .filter(move |span| span.parent_id == 0 || !span_ids.contains(&span.parent_id))
+}
+
+/// Set the `_sampling_priority_v1` metric of the local roots of a trace chunk, so Datadog keeps
+/// the trace as decided at the edge.
+pub(crate) fn set_sampling_priority(spans: &mut [dd_proto::Span], priority: SamplingPriority) {
+    for span in local_roots(spans) {
+        span.metrics.insert(
+            SAMPLING_PRIORITY_METRIC.to_string(),
+            f64::from(priority as i32),
+        );
+    }
+}
+
+/// Set the `_dd.p.tid` meta of the local roots of a trace chunk to the upper 64 bits of its
+/// 128-bit trace id, from which Datadog rebuilds the id the W3C peers of the trace know.
+pub(crate) fn set_trace_id_high(spans: &mut [dd_proto::Span], trace_id_high: u64) {
+    if trace_id_high == 0 {
+        return;
+    }
+
+    for span in local_roots(spans) {
+        span.meta.insert(
+            TRACE_ID_HIGH_META.to_string(),
+            format!("{trace_id_high:016x}"),
+        );
+    }
+}
+
+/// Set the `_dd1.sr.eausr` metric of the local roots of a trace chunk which don't have one yet,
+/// so their spans are sampled into App Analytics at `rate`.
+pub(crate) fn set_analytics_sample_rate(spans: &mut [dd_proto::Span], rate: f64) {
+    for span in local_roots(spans) {
+        span.metrics
+            .entry(ANALYTICS_SAMPLE_RATE_METRIC.to_string())
+            .or_insert(rate);
+    }
+}
+
+/// Set the `_dd.top_level` metric of the spans of a trace chunk whose parent isn't in the chunk
+/// or belongs to another service, from which Datadog computes the hits and latency of the
+/// services.
+pub(crate) fn set_top_level(spans: &mut [dd_proto::Span]) {
+    let services: HashMap<u64, &str> = spans
+        .iter()
+        .map(|span| (span.span_id, span.service.as_str()))
+        .collect();
+    let top_level: Vec<bool> = spans
+        .iter()
+        .map(|span| {
+            span.parent_id == 0
+                || services
+                    .get(&span.parent_id)
+                    .map_or(true, |service| *service != span.service)
+        })
+        .collect();
+    for (span, top_level) in spans.iter_mut().zip(top_level) {
+        if top_level {
+            span.metrics.insert(TOP_LEVEL_METRIC.to_string(), 1.0);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use opentelemetry::trace::{TraceFlags, TraceId, TraceState};
+    use opentelemetry::KeyValue;
+
+    fn span_type_of(kind: &SpanKind, attributes: &[(&'static str, &'static str)]) -> String {
+        span_type(kind, |key| {
+            attributes
+                .iter()
+                .find(|(name, _)| *name == key)
+                .map(|(_, value)| Cow::Borrowed(*value))
+        })
+        .into_owned()
+    }
+
+    #[test]
+    fn test_span_type() {
+        assert_eq!(
+            span_type_of(&SpanKind::Client, &[("span.type", "graphql")]),
+            "graphql"
+        );
+        assert_eq!(
+            span_type_of(&SpanKind::Client, &[("db.system", "postgresql")]),
+            "sql"
+        );
+        assert_eq!(
+            span_type_of(&SpanKind::Client, &[("db.system", "mongodb")]),
+            "db"
+        );
+        assert_eq!(
+            span_type_of(&SpanKind::Producer, &[("messaging.system", "kafka")]),
+            "queue"
+        );
+        assert_eq!(
+            span_type_of(&SpanKind::Client, &[("http.method", "GET")]),
+            "http"
+        );
+        assert_eq!(span_type_of(&SpanKind::Server, &[]), "web");
+        assert_eq!(span_type_of(&SpanKind::Client, &[]), "custom");
+        assert_eq!(span_type_of(&SpanKind::Internal, &[]), "custom");
+    }
+
+    #[test]
+    fn test_span_kind() {
+        assert_eq!(span_kind(&SpanKind::Server), "server");
+        assert_eq!(span_kind(&SpanKind::Client), "client");
+        assert_eq!(span_kind(&SpanKind::Producer), "producer");
+        assert_eq!(span_kind(&SpanKind::Consumer), "consumer");
+        assert_eq!(span_kind(&SpanKind::Internal), "internal");
+    }
+
+    #[test]
+    fn test_resource_filter() {
+        let filter = ResourceFilter::default();
+        assert!(filter.allows("deployment.environment"));
+        assert!(!filter.allows("service.name"));
+
+        let filter = ResourceFilter {
+            allow: Some(HashSet::from([
+                "host.name".to_string(),
+                "process.pid".to_string(),
+            ])),
+            deny: HashSet::from(["process.pid".to_string()]),
+        };
+        assert!(filter.allows("host.name"));
+        assert!(!filter.allows("process.pid"));
+        assert!(!filter.allows("deployment.environment"));
+    }
+
+    #[test]
+    fn test_unified_service_tags() {
+        let mut span = dd_proto::Span {
+            service: "gateway".to_string(),
+            ..Default::default()
+        };
+        add_attribute(&mut span, Key::new("version"), Value::from("canary"));
+        set_unified_service_tags(&mut span, "production", "1.2.3");
+
+        let tag = |name: &str| span.meta.get(name).map(String::as_str);
+        assert_eq!(tag("service"), Some("gateway"));
+        assert_eq!(tag("env"), Some("production"));
+        assert_eq!(tag("version"), Some("canary"));
+
+        let mut span = dd_proto::Span::default();
+        set_unified_service_tags(&mut span, "", "");
+        assert!(!span.meta.contains_key("env"));
+        assert!(!span.meta.contains_key("version"));
+    }
+
+    #[test]
+    fn test_enforce_limits() {
+        let mut span = dd_proto::Span {
+            name: "é".repeat(MAX_NAME_LEN + 1),
+            resource: "query GetUser".to_string(),
+            ..Default::default()
+        };
+        span.meta.insert(
+            "graphql.source".to_string(),
+            "x".repeat(MAX_META_VALUE_LEN * 2),
+        );
+        for index in 0..MAX_TAGS_PER_SPAN {
+            span.meta.insert(format!("tag.{index:04}"), String::new());
+        }
+        span.meta
+            .insert("service".to_string(), "gateway".to_string());
+
+        enforce_limits(&mut span);
+
+        assert_eq!(span.name.chars().count(), MAX_NAME_LEN);
+        assert_eq!(span.resource, "query GetUser");
+        assert_eq!(span.meta["graphql.source"].len(), MAX_META_VALUE_LEN);
+        assert_eq!(span.meta.len(), MAX_TAGS_PER_SPAN);
+        assert!(span.meta.contains_key("service"));
+        assert!(!span.meta.contains_key("tag.1023"));
+        assert_eq!(span.metrics.get(TRUNCATED_METRIC), Some(&1.0));
+
+        let mut span = dd_proto::Span {
+            name: "http.request".to_string(),
+            ..Default::default()
+        };
+        enforce_limits(&mut span);
+        assert!(span.metrics.is_empty());
+    }
+
+    #[test]
+    fn test_set_chunk_tags() {
+        let span = |span_id, parent_id, route: &str| dd_proto::Span {
+            span_id,
+            parent_id,
+            meta: BTreeMap::from([("http.route".to_string(), route.to_string())]),
+            ..Default::default()
+        };
+        let mut chunk = dd_proto::TraceChunk {
+            spans: vec![span(2, 1, "/child"), span(1, 0, "/graphql")],
+            ..Default::default()
+        };
+
+        set_chunk_tags(
+            &mut chunk,
+            &BTreeMap::from([
+                ("team".to_string(), "gateway".to_string()),
+                ("_dd.p.dm".to_string(), "-0".to_string()),
+            ]),
+            &["http.route".to_string(), "http.method".to_string()],
+            vec![("_dd.p.dm".to_string(), "-4".to_string())],
+        );
+
+        assert_eq!(
+            chunk.tags,
+            BTreeMap::from([
+                ("_dd.p.dm".to_string(), "-4".to_string()),
+                ("http.route".to_string(), "/graphql".to_string()),
+                ("team".to_string(), "gateway".to_string()),
+            ])
+        );
+    }
+
+    #[test]
+    fn test_sampling_priority() {
+        let priority = |trace_flags, trace_state| {
+            sampling_priority(&SpanContext::new(
+                TraceId::from_u128(1),
+                SpanId::from_u64(1),
+                trace_flags,
+                false,
+                trace_state,
+            ))
+        };
+        let user_reject = TraceState::from_key_value([("dd", "s:-1")]).unwrap();
+
+        assert_eq!(
+            priority(TraceFlags::SAMPLED, TraceState::default()),
+            Some(SamplingPriority::AutoKeep)
+        );
+        assert_eq!(
+            priority(TraceFlags::default(), TraceState::default()),
+            Some(SamplingPriority::AutoReject)
+        );
+        assert_eq!(
+            priority(TraceFlags::SAMPLED, user_reject),
+            Some(SamplingPriority::UserReject)
+        );
+        assert_eq!(priority(TRACE_FLAG_DEFERRED, TraceState::default()), None);
+    }
+
+    #[test]
+    fn test_manual_sampling_priority() {
+        let priority = |attributes: &[KeyValue]| {
+            let mut map = EvictedHashMap::new(128, attributes.len());
+            for attribute in attributes {
+                map.insert(attribute.clone());
+            }
+            manual_sampling_priority(&map)
+        };
+
+        assert_eq!(priority(&[]), None);
+        assert_eq!(
+            priority(&[KeyValue::new("manual.keep", true)]),
+            Some(SamplingPriority::UserKeep)
+        );
+        assert_eq!(
+            priority(&[KeyValue::new("manual.drop", "true")]),
+            Some(SamplingPriority::UserReject)
+        );
+        assert_eq!(priority(&[KeyValue::new("manual.keep", false)]), None);
+        assert_eq!(
+            priority(&[
+                KeyValue::new("manual.keep", true),
+                KeyValue::new("manual.drop", true)
+            ]),
+            Some(SamplingPriority::UserKeep)
+        );
+        assert_eq!(
+            priority(&[KeyValue::new("sampling.priority", 2)]),
+            Some(SamplingPriority::UserKeep)
+        );
+        assert_eq!(
+            priority(&[KeyValue::new("sampling.priority", "0")]),
+            Some(SamplingPriority::UserReject)
+        );
+        assert_eq!(
+            priority(&[KeyValue::new("sampling.priority", "high")]),
+            None
+        );
+
+        let mut span = dd_proto::Span::default();
+        add_attribute(&mut span, Key::new("manual.keep"), Value::Bool(true));
+        assert!(span.meta.is_empty() && span.metrics.is_empty());
+    }
+
+    #[test]
+    fn test_operation_name() {
+        let name_of = |kind: &SpanKind, attributes: &[(&'static str, &'static str)]| {
+            operation_name(kind, |key| {
+                attributes
+                    .iter()
+                    .find(|(name, _)| *name == key)
+                    .map(|(_, value)| Cow::Borrowed(*value))
+            })
+            .into_owned()
+        };
+
+        assert_eq!(
+            name_of(&SpanKind::Server, &[("operation.name", "web.request")]),
+            "web.request"
+        );
+        assert_eq!(
+            name_of(&SpanKind::Server, &[("http.request.method", "GET")]),
+            "http.server.request"
+        );
+        assert_eq!(
+            name_of(&SpanKind::Client, &[("http.method", "GET")]),
+            "http.client.request"
+        );
+        assert_eq!(
+            name_of(&SpanKind::Client, &[("db.system", "PostgreSQL")]),
+            "postgresql.query"
+        );
+        assert_eq!(
+            name_of(
+                &SpanKind::Producer,
+                &[
+                    ("messaging.system", "kafka"),
+                    ("messaging.operation", "publish")
+                ]
+            ),
+            "kafka.publish"
+        );
+        assert_eq!(
+            name_of(
+                &SpanKind::Client,
+                &[("rpc.system", "aws-api"), ("rpc.service", "S3")]
+            ),
+            "aws.s3.request"
+        );
+        assert_eq!(
+            name_of(&SpanKind::Server, &[("rpc.system", "grpc")]),
+            "grpc.server.request"
+        );
+        assert_eq!(
+            name_of(
+                &SpanKind::Client,
+                &[
+                    ("faas.invoked_provider", "aws"),
+                    ("faas.invoked_name", "resize")
+                ]
+            ),
+            "aws.resize.invoke"
+        );
+        assert_eq!(
+            name_of(&SpanKind::Server, &[("faas.trigger", "timer")]),
+            "timer.invoke"
+        );
+        assert_eq!(
+            name_of(&SpanKind::Internal, &[("graphql.operation.type", "query")]),
+            "graphql.server.request"
+        );
+        assert_eq!(
+            name_of(&SpanKind::Client, &[("network.protocol.name", "amqp")]),
+            "amqp.client.request"
+        );
+        assert_eq!(name_of(&SpanKind::Server, &[]), "server.request");
+        assert_eq!(name_of(&SpanKind::Client, &[]), "client.request");
+        assert_eq!(name_of(&SpanKind::Internal, &[]), "internal");
+        assert_eq!(name_of(&SpanKind::Consumer, &[]), "consumer");
+    }
+
+    #[test]
+    fn test_ignored_resources() {
+        let span = |span_id, parent_id, resource: &str| dd_proto::Span {
+            span_id,
+            parent_id,
+            resource: resource.to_string(),
+            ..Default::default()
+        };
+        let ignored =
+            IgnoredResources::new(&["^GET /healthz$".to_string(), "(?i)ping".to_string()]).unwrap();
+
+        assert!(ignored.ignores(&mut [span(1, 0, "GET /healthz"), span(2, 1, "SELECT ?")]));
+        assert!(ignored.ignores(&mut [span(3, 42, "PING")]));
+        // Only the resource of the root matters.
+        assert!(!ignored.ignores(&mut [span(1, 0, "GET /users"), span(2, 1, "GET /healthz")]));
+        assert!(!IgnoredResources::default().ignores(&mut [span(1, 0, "GET /healthz")]));
+        // Unanchored patterns match anywhere in the resource.
+        assert!(!ignored.ignores(&mut [span(1, 0, "GET /healthz/ready")]));
+        assert!(!ignored.ignores(&mut [span(1, 0, "get /healthz")]));
+        assert!(ignored.ignores(&mut [span(1, 0, "GET /ping/ready")]));
+
+        assert!(matches!(
+            IgnoredResources::new(&["(".to_string()]),
+            Err(Error::Other(_))
+        ));
+    }
+
+    #[test]
+    fn test_set_sampling_priority() {
+        let span = |span_id, parent_id| dd_proto::Span {
+            span_id,
+            parent_id,
+            ..Default::default()
+        };
+        // A root, its child, and a span whose parent is in another service.
+        let mut spans = vec![span(1, 0), span(2, 1), span(3, 42)];
+
+        set_sampling_priority(&mut spans, SamplingPriority::UserKeep);
+
+        let priorities: Vec<Option<f64>> = spans
+            .iter()
+            .map(|span| span.metrics.get(SAMPLING_PRIORITY_METRIC).copied())
+            .collect();
+        assert_eq!(priorities, vec![Some(2.0), None, Some(2.0)]);
+    }
+
+    #[test]
+    fn test_set_trace_id_high() {
+        let span = |span_id, parent_id| dd_proto::Span {
+            span_id,
+            parent_id,
+            ..Default::default()
+        };
+        let mut spans = vec![span(1, 0), span(2, 1)];
+
+        set_trace_id_high(&mut spans, 0x6553_9a3f);
+        assert_eq!(
+            spans[0].meta.get(TRACE_ID_HIGH_META).map(String::as_str),
+            Some("0000000065539a3f")
+        );
+        assert_eq!(spans[1].meta.get(TRACE_ID_HIGH_META), None);
+
+        // The ids of 64 bits, e.g. propagated by the Datadog headers, don't have one.
+        let mut spans = vec![span(1, 0)];
+        set_trace_id_high(&mut spans, 0);
+        assert!(spans[0].meta.is_empty());
+    }
+
+    #[test]
+    fn test_add_attribute() {
+        let mut span = dd_proto::Span::default();
+        add_attribute(&mut span, Key::new("db.rows"), Value::I64(20));
+        add_attribute(&mut span, Key::new("graphql.complexity"), Value::F64(12.5));
+        add_attribute(&mut span, Key::new("http.method"), Value::from("POST"));
+        add_attribute(&mut span, Key::new("cache.hit"), Value::Bool(true));
+
+        assert_eq!(span.metrics.get("db.rows"), Some(&20.0));
+        assert_eq!(span.metrics.get("graphql.complexity"), Some(&12.5));
+        assert_eq!(
+            span.meta.get("http.method").map(String::as_str),
+            Some("POST")
+        );
+        assert_eq!(span.meta.get("cache.hit").map(String::as_str), Some("true"));
+        assert!(!span.meta.contains_key("db.rows"));
+    }
+
+    #[test]
+    fn test_serverless_tags() {
+        let mut span = dd_proto::Span::default();
+        add_attribute(&mut span, Key::new("faas.trigger"), Value::from("http"));
+        add_attribute(
+            &mut span,
+            Key::new("faas.invocation_id"),
+            Value::from("8f2c"),
+        );
+        add_attribute(&mut span, Key::new("faas.coldstart"), Value::Bool(true));
+        add_attribute(&mut span, Key::new("cloud.region"), Value::from("weur"));
+        add_attribute(
+            &mut span,
+            Key::new("cloud.availability_zone"),
+            Value::from("CDG"),
+        );
+
+        let tag = |name: &str| span.meta.get(name).map(String::as_str);
+        assert_eq!(tag("function_trigger.event_source"), Some("http"));
+        assert_eq!(tag("request_id"), Some("8f2c"));
+        assert_eq!(tag("cold_start"), Some("true"));
+        assert_eq!(tag("region"), Some("weur"));
+        assert_eq!(tag("datacenter"), Some("CDG"));
+        assert_eq!(tag("faas.trigger"), None);
+    }
+
+    #[test]
+    fn test_graphql_conventions() {
+        let graphql_span = |kind: &SpanKind| {
+            let mut span = dd_proto::Span {
+                resource: "gateway::graphql".to_string(),
+                ..Default::default()
+            };
+            add_attribute(
+                &mut span,
+                Key::new("graphql.operation.name"),
+                Value::from("GetUser"),
+            );
+            add_attribute(
+                &mut span,
+                Key::new("graphql.operation.type"),
+                Value::from("query"),
+            );
+            add_attribute(
+                &mut span,
+                Key::new("graphql.document"),
+                Value::from("query GetUser { user { id } }"),
+            );
+            set_graphql_conventions(&mut span, kind);
+            span
+        };
+
+        let span = graphql_span(&SpanKind::Server);
+        assert_eq!(span.resource, "query GetUser");
+        assert_eq!(
+            span.meta.get("graphql.source").map(String::as_str),
+            Some("query GetUser { user { id } }")
+        );
+        assert!(!span.meta.contains_key("graphql.document"));
+
+        assert_eq!(
+            graphql_span(&SpanKind::Internal).resource,
+            "gateway::graphql"
+        );
+    }
+
+    #[test]
+    fn test_db_statement() {
+        let mut span = dd_proto::Span::default();
+        add_attribute(
+            &mut span,
+            Key::new("db.statement"),
+            Value::from("SELECT * FROM users WHERE id = 42"),
+        );
+        assert_eq!(span.resource, "SELECT * FROM users WHERE id = ?");
+        assert_eq!(
+            span.meta.get("db.statement").map(String::as_str),
+            Some("SELECT * FROM users WHERE id = ?")
+        );
+
+        let mut span = dd_proto::Span {
+            resource: "d1::query".to_string(),
+            ..Default::default()
+        };
+        add_attribute(&mut span, Key::new("db.statement"), Value::from("SELECT 1"));
+        assert_eq!(span.resource, "d1::query");
+    }
+
+    #[test]
+    fn test_http_conventions() {
+        let http_span = |kind: &SpanKind, status: i64| {
+            let mut span = dd_proto::Span::default();
+            add_attribute(
+                &mut span,
+                Key::new("http.request.method"),
+                Value::from("GET"),
+            );
+            add_attribute(&mut span, Key::new("http.route"), Value::from("/users/:id"));
+            add_attribute(
+                &mut span,
+                Key::new("http.response.status_code"),
+                Value::I64(status),
+            );
+            add_attribute(
+                &mut span,
+                Key::new("url.full"),
+                Value::from("https://example.com/users/1"),
+            );
+            set_http_conventions(&mut span, kind);
+            span
+        };
+
+        let span = http_span(&SpanKind::Server, 200);
+        assert_eq!(span.resource, "GET /users/:id");
+        assert_eq!(span.error, 0);
+        assert_eq!(
+            span.meta.get("http.method").map(String::as_str),
+            Some("GET")
+        );
+        assert_eq!(
+            span.meta.get("http.status_code").map(String::as_str),
+            Some("200")
+        );
+        assert_eq!(
+            span.meta.get("http.url").map(String::as_str),
+            Some("https://example.com/users/1")
+        );
+        assert!(span.metrics.is_empty());
+
+        assert_eq!(http_span(&SpanKind::Server, 503).error, 1);
+        assert_eq!(http_span(&SpanKind::Client, 503).error, 0);
+
+        let mut span = dd_proto::Span {
+            resource: "gateway::graphql".to_string(),
+            ..http_span(&SpanKind::Server, 200)
+        };
+        set_http_conventions(&mut span, &SpanKind::Server);
+        assert_eq!(span.resource, "gateway::graphql");
+
+        let mut span = http_span(&SpanKind::Server, 200);
+        span.resource.clear();
+        span.meta.remove("http.route");
+        set_http_conventions(&mut span, &SpanKind::Server);
+        assert_eq!(span.resource, "GET /users/?");
+    }
+
+    #[test]
+    fn test_normalize_tag_key() {
+        assert!(matches!(
+            normalize_tag_key("http.request.header.x-request-id"),
+            Cow::Borrowed(_)
+        ));
+        assert_eq!(normalize_tag_key("User Id"), "user_id");
+        assert_eq!(normalize_tag_key("__cache  hit!!"), "cache_hit");
+        assert_eq!(normalize_tag_key("Région.Nom"), "région.nom");
+        assert_eq!(normalize_tag_key("emoji 🚀 key"), "emoji_key");
+        assert_eq!(normalize_tag_key("42"), "");
+        assert_eq!(normalize_tag_key(&"a".repeat(300)).len(), MAX_TAG_KEY_LEN);
+
+        let mut span = dd_proto::Span::default();
+        add_attribute(&mut span, Key::new("Cache Hit"), Value::Bool(true));
+        add_attribute(&mut span, Key::new("Rows Read"), Value::I64(3));
+        add_attribute(&mut span, Key::new("!!"), Value::from("dropped"));
+        assert_eq!(span.meta.get("cache_hit").map(String::as_str), Some("true"));
+        assert_eq!(span.metrics.get("rows_read"), Some(&3.0));
+        assert_eq!(span.meta.len(), 1);
+    }
+
+    #[test]
+    fn test_measured() {
+        let measured = |key: &'static str, value: Value| {
+            let mut span = dd_proto::Span::default();
+            add_attribute(&mut span, Key::new(key), value);
+            assert!(span.meta.is_empty());
+            span.metrics.get(MEASURED_METRIC).copied()
+        };
+
+        assert_eq!(measured("datadog.measured", Value::Bool(true)), Some(1.0));
+        assert_eq!(measured("_dd.measured", Value::I64(1)), Some(1.0));
+        assert_eq!(measured("datadog.measured", Value::from("true")), Some(1.0));
+        assert_eq!(measured("datadog.measured", Value::Bool(false)), None);
+    }
+
+    #[test]
+    fn test_analytics_sample_rate() {
+        let rate = |value: Value| {
+            let mut span = dd_proto::Span::default();
+            add_attribute(&mut span, Key::new(ANALYTICS_EVENT_ATTRIBUTE), value);
+            assert!(span.meta.is_empty());
+            span.metrics.get(ANALYTICS_SAMPLE_RATE_METRIC).copied()
+        };
+        assert_eq!(rate(Value::Bool(true)), Some(1.0));
+        assert_eq!(rate(Value::Bool(false)), Some(0.0));
+        assert_eq!(rate(Value::F64(0.25)), Some(0.25));
+        assert_eq!(rate(Value::I64(3)), Some(1.0));
+        assert_eq!(rate(Value::from("0.5")), Some(0.5));
+        assert_eq!(rate(Value::from("sometimes")), None);
+
+        let span = |span_id, parent_id| dd_proto::Span {
+            span_id,
+            parent_id,
+            ..Default::default()
+        };
+        let mut spans = vec![span(1, 0), span(2, 1), span(3, 42)];
+        spans[2]
+            .metrics
+            .insert(ANALYTICS_SAMPLE_RATE_METRIC.to_string(), 1.0);
+
+        set_analytics_sample_rate(&mut spans, 0.1);
+
+        let rates: Vec<Option<f64>> = spans
+            .iter()
+            .map(|span| span.metrics.get(ANALYTICS_SAMPLE_RATE_METRIC).copied())
+            .collect();
+        assert_eq!(rates, vec![Some(0.1), None, Some(1.0)]);
+    }
+
+    #[test]
+    fn test_set_top_level() {
+        let span = |span_id, parent_id, service: &str| dd_proto::Span {
+            span_id,
+            parent_id,
+            service: service.to_string(),
+            ..Default::default()
+        };
+        // A root, its child in the same service, a child in another service and a span whose
+        // parent is in another chunk.
+        let mut spans = vec![
+            span(1, 0, "gateway"),
+            span(2, 1, "gateway"),
+            span(3, 1, "postgres"),
+            span(4, 42, "gateway"),
+        ];
+
+        set_top_level(&mut spans);
+
+        let top_level: Vec<Option<f64>> = spans
+            .iter()
+            .map(|span| span.metrics.get(TOP_LEVEL_METRIC).copied())
+            .collect();
+        assert_eq!(top_level, vec![Some(1.0), None, Some(1.0), Some(1.0)]);
+    }
+
+    #[test]
+    fn test_set_error() {
+        let exception = Event::new(
+            EXCEPTION_EVENT,
+            SystemTime::UNIX_EPOCH,
+            vec![
+                KeyValue::new("exception.message", "connection refused"),
+                KeyValue::new("exception.type", "std::io::Error"),
+            ],
+            0,
+        );
+        let retry = Event::new("retry", SystemTime::UNIX_EPOCH, Vec::new(), 0);
+
+        let mut span = dd_proto::Span::default();
+        set_error(
+            &mut span,
+            &StatusCode::Unset,
+            "",
+            [&retry, &exception],
+            &Redaction::default(),
+        );
+        assert_eq!(span.error, 1);
+        assert_eq!(
+            span.meta.get("error.msg").map(String::as_str),
+            Some("connection refused")
+        );
+        assert_eq!(
+            span.meta.get("error.type").map(String::as_str),
+            Some("std::io::Error")
+        );
+        assert!(!span.meta.contains_key("error.stack"));
+
+        let mut span = dd_proto::Span::default();
+        set_error(
+            &mut span,
+            &StatusCode::Error,
+            "upstream timed out",
+            [&retry],
+            &Redaction::default(),
+        );
+        assert_eq!(span.error, 1);
+        assert_eq!(
+            span.meta.get("error.msg").map(String::as_str),
+            Some("upstream timed out")
+        );
+
+        let mut span = dd_proto::Span::default();
+        set_error(&mut span, &StatusCode::Ok, "", [], &Redaction::default());
+        assert_eq!(span.error, 0);
+        assert!(span.meta.is_empty());
+
+        let redaction = Redaction::new(vec!["exception.message".to_string()], &[]).unwrap();
+        let mut span = dd_proto::Span::default();
+        set_error(&mut span, &StatusCode::Unset, "", [&exception], &redaction);
+        assert_eq!(
+            span.meta.get("error.msg").map(String::as_str),
+            Some(REDACTED)
+        );
+        assert_eq!(
+            span.meta.get("error.type").map(String::as_str),
+            Some("std::io::Error")
+        );
+    }
+
+    #[test]
+    fn test_set_events() {
+        let event = Event::new(
+            "cache miss",
+            SystemTime::UNIX_EPOCH + std::time::Duration::from_millis(1500),
+            vec![
+                KeyValue::new("cache.key", "user:1"),
+                KeyValue::new("cache.ttl", 60),
+                KeyValue::new("cache.stale", true),
+            ],
+            0,
+        );
+
+        let mut span = dd_proto::Span::default();
+        set_events(&mut span, [&event], &Redaction::default());
+        let events: serde_json::Value = serde_json::from_str(&span.meta[EVENTS_META]).unwrap();
+        assert_eq!(
+            events,
+            serde_json::json!([{
+                "name": "cache miss",
+                "time_unix_nano": 1_500_000_000_u64,
+                "attributes": {
+                    "cache.key": "user:1",
+                    "cache.ttl": 60,
+                    "cache.stale": true,
+                },
+            }])
+        );
+
+        let mut span = dd_proto::Span::default();
+        set_events(&mut span, [], &Redaction::default());
+        assert!(span.meta.is_empty());
+
+        let redaction = Redaction::new(Vec::new(), &["^cache\\.key$".to_string()]).unwrap();
+        let mut span = dd_proto::Span::default();
+        set_events(&mut span, [&event], &redaction);
+        let events: serde_json::Value = serde_json::from_str(&span.meta[EVENTS_META]).unwrap();
+        assert_eq!(events[0]["attributes"]["cache.key"], REDACTED);
+        assert_eq!(events[0]["attributes"]["cache.ttl"], 60);
+    }
+
+    #[test]
+    fn test_set_span_links() {
+        let link = |trace_state| {
+            let span_context = SpanContext::new(
+                TraceId::from_u128(0x0102_0304_0506_0708_090a_0b0c_0d0e_0f10),
+                SpanId::from_u64(0x2a),
+                TraceFlags::SAMPLED,
+                true,
+                trace_state,
+            );
+            Link::new(span_context, vec![KeyValue::new("link.reason", "batch")])
+        };
+        let trace_state = TraceState::from_key_value([("dd", "s:2")]).unwrap();
+
+        let mut span = dd_proto::Span::default();
+        set_span_links(
+            &mut span,
+            [&link(trace_state), &link(TraceState::default())],
+            &Redaction::default(),
+        );
+        let links: serde_json::Value = serde_json::from_str(&span.meta[SPAN_LINKS_META]).unwrap();
+        assert_eq!(
+            links,
+            serde_json::json!([
+                {
+                    "trace_id": "0102030405060708090a0b0c0d0e0f10",
+                    "span_id": "000000000000002a",
+                    "attributes": { "link.reason": "batch" },
+                    "tracestate": "dd=s:2",
+                    "flags": 0x8000_0001_u32,
+                },
+                {
+                    "trace_id": "0102030405060708090a0b0c0d0e0f10",
+                    "span_id": "000000000000002a",
+                    "attributes": { "link.reason": "batch" },
+                    "flags": 0x8000_0001_u32,
+                },
+            ])
+        );
+
+        let mut span = dd_proto::Span::default();
+        set_span_links(&mut span, [], &Redaction::default());
+        assert!(span.meta.is_empty());
+
+        let redaction = Redaction::new(vec!["link.reason".to_string()], &[]).unwrap();
+        let mut span = dd_proto::Span::default();
+        set_span_links(&mut span, [&link(TraceState::default())], &redaction);
+        let links: serde_json::Value = serde_json::from_str(&span.meta[SPAN_LINKS_META]).unwrap();
+        assert_eq!(links[0]["attributes"]["link.reason"], REDACTED);
+    }
+}
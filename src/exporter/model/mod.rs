@@ -1,5 +1,8 @@
 use opentelemetry::sdk::export::ExportError;
 
+pub(crate) mod v04;
+pub(crate) mod v07;
+
 /// Wrap type for errors from opentelemetry datadog exporter
 #[derive(Debug, thiserror::Error)]
 pub enum Error {
@@ -7,21 +10,123 @@ pub enum Error {
     #[error("message pack error")]
     MessagePackError,
     /// No http client founded. User should provide one or enable features
-    #[error("http client must be set, users can enable reqwest or reqwest feature to use http client implementation within create")]
+    #[error("http client must be set, users can enable the reqwest-client or worker-client feature to use http client implementation within create")]
     NoHttpClient,
     /// Http requests failed with following errors
     #[error(transparent)]
     RequestError(#[from] http::Error),
+    /// The endpoint is not an absolute http(s) url
+    #[error("invalid endpoint {0}")]
+    InvalidEndpoint(String),
     /// The Uri was invalid
     #[error(transparent)]
     InvalidUri(#[from] http::uri::InvalidUri),
+    /// The trace intake couldn't be reached
+    #[error("failed to send traces: {0}")]
+    Transport(String),
+    /// The trace intake didn't answer in time
+    #[error("trace export timed out")]
+    Timeout,
+    /// The trace intake rejected the payload, e.g. with a 403 because of a wrong API key
+    #[error("trace intake rejected the payload with {status}: {body}")]
+    ClientError {
+        /// HTTP status code of the response
+        status: u16,
+        /// Beginning of the body of the response
+        body: String,
+    },
+    /// The trace intake failed to handle the payload
+    #[error("trace intake failed with {status}: {body}")]
+    ServerError {
+        /// HTTP status code of the response
+        status: u16,
+        /// Beginning of the body of the response
+        body: String,
+    },
+    /// The encoded payload isn't as long as computed before encoding it, e.g. truncated
+    #[error("encoded payload is {actual} bytes instead of {expected}")]
+    PayloadLengthMismatch {
+        /// Length computed from the payload
+        expected: usize,
+        /// Length of the encoded payload
+        actual: usize,
+    },
+    /// Datadog rejected the API key
+    #[error("the Datadog API key is invalid")]
+    InvalidApiKey,
+    /// The propagation style is not one of `datadog`, `tracecontext`, `b3multi` or `none`
+    #[error("unknown propagation style: {0}")]
+    InvalidPropagationStyle(String),
     /// Other errors
     #[error("{0}")]
     Other(String),
 }
 
+/// Longest response body kept in an error, the intake may answer with whole HTML pages.
+const MAX_ERROR_BODY_LEN: usize = 512;
+
+impl Error {
+    /// The error matching an unsuccessful response from the trace intake.
+    pub(crate) fn from_response(status: u16, mut body: String) -> Self {
+        if body.len() > MAX_ERROR_BODY_LEN {
+            let mut end = MAX_ERROR_BODY_LEN;
+            while !body.is_char_boundary(end) {
+                end -= 1;
+            }
+            body.truncate(end);
+            body.push_str("...");
+        }
+
+        if (500..600).contains(&status) {
+            Error::ServerError { status, body }
+        } else {
+            Error::ClientError { status, body }
+        }
+    }
+
+    /// Whether the export may succeed if attempted again, i.e. on connection errors, timeouts,
+    /// rate limiting and server errors.
+    #[must_use]
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            Error::Transport(_) | Error::Timeout | Error::ServerError { .. } => true,
+            Error::ClientError { status, .. } => matches!(status, 408 | 429),
+            _ => false,
+        }
+    }
+}
+
 impl ExportError for Error {
     fn exporter_name(&self) -> &'static str {
         "datadog-traces"
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_from_response() {
+        let err = Error::from_response(403, "Forbidden".to_string());
+        assert!(matches!(&err, Error::ClientError { status: 403, body } if body == "Forbidden"));
+        assert!(!err.is_retryable());
+
+        assert!(Error::from_response(429, String::new()).is_retryable());
+        assert!(matches!(
+            Error::from_response(502, String::new()),
+            Error::ServerError { status: 502, .. }
+        ));
+
+        let body = "é".repeat(MAX_ERROR_BODY_LEN);
+        let err = Error::from_response(500, body);
+        assert!(
+            matches!(
+                &err,
+                Error::ServerError { body, .. }
+                    if body.len() <= MAX_ERROR_BODY_LEN + 3 && body.ends_with("...")
+            ),
+            "{err:?}"
+        );
+    }
+}
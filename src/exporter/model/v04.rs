@@ -0,0 +1,106 @@
+//! Msgpack encoding of the `/v0.4/traces` endpoint of the Datadog Agent.
+
+use rmp::encode::{self, ValueWriteError};
+
+use super::Error;
+use crate::dd_proto;
+
+impl From<ValueWriteError> for Error {
+    fn from(_: ValueWriteError) -> Self {
+        Error::MessagePackError
+    }
+}
+
+/// Encode the chunks as an array of traces, each being an array of spans.
+pub(crate) fn encode(chunks: &[dd_proto::TraceChunk]) -> Result<Vec<u8>, Error> {
+    let mut buf = Vec::new();
+
+    encode::write_array_len(&mut buf, len(chunks.len())?)?;
+    for chunk in chunks {
+        encode::write_array_len(&mut buf, len(chunk.spans.len())?)?;
+        for span in &chunk.spans {
+            encode_span(&mut buf, span)?;
+        }
+    }
+
+    Ok(buf)
+}
+
+pub(super) fn encode_span(buf: &mut Vec<u8>, span: &dd_proto::Span) -> Result<(), Error> {
+    encode::write_map_len(buf, 12)?;
+
+    encode::write_str(buf, "service")?;
+    encode::write_str(buf, &span.service)?;
+    encode::write_str(buf, "name")?;
+    encode::write_str(buf, &span.name)?;
+    encode::write_str(buf, "resource")?;
+    encode::write_str(buf, &span.resource)?;
+    encode::write_str(buf, "type")?;
+    encode::write_str(buf, &span.r#type)?;
+    encode::write_str(buf, "trace_id")?;
+    encode::write_uint(buf, span.trace_id)?;
+    encode::write_str(buf, "span_id")?;
+    encode::write_uint(buf, span.span_id)?;
+    encode::write_str(buf, "parent_id")?;
+    encode::write_uint(buf, span.parent_id)?;
+    encode::write_str(buf, "start")?;
+    encode::write_sint(buf, span.start)?;
+    encode::write_str(buf, "duration")?;
+    encode::write_sint(buf, span.duration)?;
+    encode::write_str(buf, "error")?;
+    encode::write_sint(buf, i64::from(span.error))?;
+
+    encode::write_str(buf, "meta")?;
+    encode::write_map_len(buf, len(span.meta.len())?)?;
+    for (key, value) in &span.meta {
+        encode::write_str(buf, key)?;
+        encode::write_str(buf, value)?;
+    }
+
+    encode::write_str(buf, "metrics")?;
+    encode::write_map_len(buf, len(span.metrics.len())?)?;
+    for (key, value) in &span.metrics {
+        encode::write_str(buf, key)?;
+        encode::write_f64(buf, *value)?;
+    }
+
+    Ok(())
+}
+
+pub(super) fn len(len: usize) -> Result<u32, Error> {
+    u32::try_from(len).map_err(|_| Error::MessagePackError)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rmp::decode;
+    use std::collections::BTreeMap;
+
+    #[test]
+    fn test_encode() {
+        let span = dd_proto::Span {
+            service: "api".to_string(),
+            name: "request".to_string(),
+            trace_id: 1234,
+            span_id: 12,
+            meta: BTreeMap::from([("http.method".to_string(), "GET".to_string())]),
+            ..Default::default()
+        };
+        let chunks = vec![dd_proto::TraceChunk {
+            spans: vec![span.clone(), span],
+            ..Default::default()
+        }];
+
+        let encoded = encode(&chunks).unwrap();
+
+        let mut rd = encoded.as_slice();
+        assert_eq!(decode::read_array_len(&mut rd).unwrap(), 1);
+        assert_eq!(decode::read_array_len(&mut rd).unwrap(), 2);
+        assert_eq!(decode::read_map_len(&mut rd).unwrap(), 12);
+
+        let mut key = [0; 16];
+        assert_eq!(decode::read_str(&mut rd, &mut key).unwrap(), "service");
+        assert_eq!(decode::read_str(&mut rd, &mut key).unwrap(), "api");
+    }
+}
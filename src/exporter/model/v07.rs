@@ -0,0 +1,109 @@
+//! Encoding of the `/v0.7/traces` endpoint of the Datadog Agent, a `TracerPayload` in msgpack.
+
+use rmp::encode;
+use std::collections::BTreeMap;
+
+use super::v04::{encode_span, len};
+use super::Error;
+use crate::dd_proto;
+
+/// Encode `tracer` with the payload level fields the agent reads from the same map.
+pub(crate) fn encode(
+    tracer: &dd_proto::TracerPayload,
+    env: &str,
+    hostname: &str,
+    tags: &BTreeMap<String, String>,
+) -> Result<Vec<u8>, Error> {
+    let mut buf = Vec::new();
+
+    encode::write_map_len(&mut buf, 10)?;
+    encode::write_str(&mut buf, "container_id")?;
+    encode::write_str(&mut buf, &tracer.container_id)?;
+    encode::write_str(&mut buf, "language_name")?;
+    encode::write_str(&mut buf, &tracer.language_name)?;
+    encode::write_str(&mut buf, "language_version")?;
+    encode::write_str(&mut buf, &tracer.language_version)?;
+    encode::write_str(&mut buf, "tracer_version")?;
+    encode::write_str(&mut buf, &tracer.tracer_version)?;
+    encode::write_str(&mut buf, "runtime_id")?;
+    encode::write_str(&mut buf, &tracer.runtime_id)?;
+    encode::write_str(&mut buf, "env")?;
+    encode::write_str(&mut buf, env)?;
+    encode::write_str(&mut buf, "hostname")?;
+    encode::write_str(&mut buf, hostname)?;
+    encode::write_str(&mut buf, "app_version")?;
+    encode::write_str(&mut buf, &tracer.app_version)?;
+    encode::write_str(&mut buf, "tags")?;
+    encode_tags(&mut buf, tags)?;
+
+    encode::write_str(&mut buf, "chunks")?;
+    encode::write_array_len(&mut buf, len(tracer.chunks.len())?)?;
+    for chunk in &tracer.chunks {
+        encode_chunk(&mut buf, chunk)?;
+    }
+
+    Ok(buf)
+}
+
+fn encode_chunk(buf: &mut Vec<u8>, chunk: &dd_proto::TraceChunk) -> Result<(), Error> {
+    encode::write_map_len(buf, 5)?;
+
+    encode::write_str(buf, "priority")?;
+    encode::write_sint(buf, i64::from(chunk.priority))?;
+    encode::write_str(buf, "origin")?;
+    encode::write_str(buf, &chunk.origin)?;
+    encode::write_str(buf, "tags")?;
+    encode_tags(buf, &chunk.tags)?;
+    encode::write_str(buf, "dropped_trace")?;
+    encode::write_bool(buf, chunk.dropped_trace)?;
+
+    encode::write_str(buf, "spans")?;
+    encode::write_array_len(buf, len(chunk.spans.len())?)?;
+    for span in &chunk.spans {
+        encode_span(buf, span)?;
+    }
+
+    Ok(())
+}
+
+fn encode_tags(buf: &mut Vec<u8>, tags: &BTreeMap<String, String>) -> Result<(), Error> {
+    encode::write_map_len(buf, len(tags.len())?)?;
+    for (key, value) in tags {
+        encode::write_str(buf, key)?;
+        encode::write_str(buf, value)?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rmp::decode;
+
+    #[test]
+    fn test_encode() {
+        let tracer = dd_proto::TracerPayload {
+            language_name: "rust".to_string(),
+            chunks: vec![dd_proto::TraceChunk {
+                priority: 1,
+                spans: vec![dd_proto::Span::default()],
+                ..Default::default()
+            }],
+            ..Default::default()
+        };
+
+        let encoded = encode(&tracer, "prod", "", &BTreeMap::new()).unwrap();
+
+        let mut rd = encoded.as_slice();
+        let mut buf = [0; 32];
+        assert_eq!(decode::read_map_len(&mut rd).unwrap(), 10);
+        assert_eq!(decode::read_str(&mut rd, &mut buf).unwrap(), "container_id");
+        assert_eq!(decode::read_str(&mut rd, &mut buf).unwrap(), "");
+        assert_eq!(
+            decode::read_str(&mut rd, &mut buf).unwrap(),
+            "language_name"
+        );
+        assert_eq!(decode::read_str(&mut rd, &mut buf).unwrap(), "rust");
+    }
+}
@@ -0,0 +1,114 @@
+//! Obfuscation of the SQL statements of the database spans, as done by the Datadog Agent, so the
+//! values of the queries don't leak into the resources and tags.
+
+use std::iter::Peekable;
+use std::str::Chars;
+
+/// `query` with its string and numeric literals replaced by `?`, its comments removed and its
+/// whitespace collapsed, e.g. `SELECT * FROM users WHERE id = ?` for
+/// `SELECT * FROM users WHERE id = 42`. Quoted identifiers and `$1` placeholders are kept.
+pub(crate) fn obfuscate_sql(query: &str) -> String {
+    let mut obfuscated = String::with_capacity(query.len());
+    let mut chars = query.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '\'' => {
+                skip_string(&mut chars);
+                obfuscated.push('?');
+            }
+            '"' | '`' => {
+                obfuscated.push(c);
+                for identifier in chars.by_ref() {
+                    obfuscated.push(identifier);
+                    if identifier == c {
+                        break;
+                    }
+                }
+            }
+            '-' if chars.peek() == Some(&'-') => {
+                for c in chars.by_ref() {
+                    if c == '\n' {
+                        break;
+                    }
+                }
+                push_space(&mut obfuscated);
+            }
+            '/' if chars.peek() == Some(&'*') => {
+                chars.next();
+                let mut previous = None;
+                for c in chars.by_ref() {
+                    if previous == Some('*') && c == '/' {
+                        break;
+                    }
+                    previous = Some(c);
+                }
+                push_space(&mut obfuscated);
+            }
+            c if c.is_ascii_digit() && !obfuscated.ends_with(is_identifier) => {
+                while chars
+                    .peek()
+                    .map_or(false, |&c| c.is_ascii_alphanumeric() || c == '.')
+                {
+                    chars.next();
+                }
+                obfuscated.push('?');
+            }
+            c if c.is_whitespace() => push_space(&mut obfuscated),
+            c => obfuscated.push(c),
+        }
+    }
+    obfuscated.trim().to_string()
+}
+
+/// Skip the rest of a string literal, whose quotes are escaped by doubling them or with a
+/// backslash.
+fn skip_string(chars: &mut Peekable<Chars<'_>>) {
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => {
+                chars.next();
+            }
+            '\'' if chars.peek() == Some(&'\'') => {
+                chars.next();
+            }
+            '\'' => break,
+            _ => {}
+        }
+    }
+}
+
+fn push_space(obfuscated: &mut String) {
+    if !obfuscated.is_empty() && !obfuscated.ends_with(' ') {
+        obfuscated.push(' ');
+    }
+}
+
+/// Whether a digit after `c` belongs to an identifier or a placeholder, e.g. `table1` or `$1`.
+fn is_identifier(c: char) -> bool {
+    c.is_alphanumeric() || c == '_' || c == '$'
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_obfuscate_sql() {
+        assert_eq!(
+            obfuscate_sql("SELECT * FROM users WHERE email = 'jane@example.com' AND age > 42"),
+            "SELECT * FROM users WHERE email = ? AND age > ?"
+        );
+        assert_eq!(
+            obfuscate_sql("INSERT INTO logs (message, level) VALUES ('it''s \\'here\\'', 3.5)"),
+            "INSERT INTO logs (message, level) VALUES (?, ?)"
+        );
+        assert_eq!(
+            obfuscate_sql("SELECT \"user 1\".id FROM table1 WHERE id = $1 LIMIT 0x10"),
+            "SELECT \"user 1\".id FROM table1 WHERE id = $1 LIMIT ?"
+        );
+        assert_eq!(
+            obfuscate_sql("SELECT 1 -- token=secret\nFROM /* user 7 */ dual"),
+            "SELECT ? FROM dual"
+        );
+    }
+}
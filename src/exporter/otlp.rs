@@ -0,0 +1,334 @@
+use async_trait::async_trait;
+use opentelemetry::sdk::export::trace::{ExportResult, SpanData, SpanExporter};
+use opentelemetry::sdk::InstrumentationLibrary;
+use opentelemetry::sdk::Resource;
+use opentelemetry::trace::{SpanId, SpanKind, StatusCode, TraceId};
+use opentelemetry::{Array, Key, Value};
+use serde_json::json;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+
+use super::transport::{ExportRequest, Transport};
+use super::{default_transport, endpoint_url, send, send_future, Error, CONTENT_TYPE_HEADER};
+use reqwest::Client;
+
+const OTLP_TRACES_PATH: &str = "v1/traces";
+const OTLP_CONTENT_TYPE: &str = "application/json";
+
+/// The spans of a resource, by instrumentation library.
+type ScopeSpans<'a> = Vec<(&'a InstrumentationLibrary, Vec<&'a SpanData>)>;
+
+/// Sends the spans to an OpenTelemetry collector with OTLP/HTTP, in its JSON encoding.
+///
+/// Meant as the fallback of the Datadog export, see
+/// [`DatadogPipelineBuilder::with_fallback_exporter`](super::DatadogPipelineBuilder::with_fallback_exporter):
+/// the batches the intake rejects are re-encoded and sent to the collector, so traces are not
+/// lost during intake incidents.
+#[derive(Debug)]
+pub struct OtlpHttpExporter {
+    transport: Transport,
+    url: String,
+    headers: Vec<(String, String)>,
+    timeout: Option<Duration>,
+}
+
+impl OtlpHttpExporter {
+    /// Export to the collector at `endpoint`, e.g. `http://localhost:4318`, with the http client
+    /// of the enabled client feature. The spans are posted to its `/v1/traces` path.
+    ///
+    /// # Errors
+    ///
+    /// If `endpoint` isn't an http(s) url or no client feature is enabled.
+    pub fn new(endpoint: &str) -> Result<Self, Error> {
+        Ok(OtlpHttpExporter {
+            transport: default_transport().ok_or(Error::NoHttpClient)?,
+            url: endpoint_url(endpoint, OTLP_TRACES_PATH)?.to_string(),
+            headers: Vec::new(),
+            timeout: None,
+        })
+    }
+
+    /// Choose the http client sending the spans.
+    #[must_use]
+    pub fn with_http_client(mut self, client: Arc<Client>) -> Self {
+        self.transport = Transport::Reqwest(client);
+        self
+    }
+
+    /// Send the header `name` with every request, e.g. the credentials of the collector.
+    #[must_use]
+    pub fn with_header(mut self, name: impl Into<String>, value: impl Into<String>) -> Self {
+        self.headers.push((name.into(), value.into()));
+        self
+    }
+
+    /// Give up on a request after `timeout`.
+    #[must_use]
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+}
+
+#[async_trait]
+impl SpanExporter for OtlpHttpExporter {
+    async fn export(&mut self, batch: Vec<SpanData>) -> ExportResult {
+        let body = serde_json::to_vec(&export_request(&batch))
+            .map_err(|e| Error::Other(format!("failed to encode the OTLP request: {e}")))?;
+        let request = self.headers.iter().fold(
+            ExportRequest::post(self.url.clone(), body)
+                .header(CONTENT_TYPE_HEADER, OTLP_CONTENT_TYPE),
+            |request, (name, value)| request.header(name.clone(), value.clone()),
+        );
+        let response = send_future(send(&self.transport, request, self.timeout)).await?;
+        response.check()?;
+        Ok(())
+    }
+}
+
+/// The `ExportTraceServiceRequest` of `batch`, its spans grouped by resource and then by
+/// instrumentation library.
+fn export_request(batch: &[SpanData]) -> serde_json::Value {
+    let mut resources: Vec<(Option<&Arc<Resource>>, ScopeSpans<'_>)> = Vec::new();
+    for span in batch {
+        let resource = span.resource.as_ref();
+        let index = resources
+            .iter()
+            .position(|(existing, _)| match (existing, resource) {
+                (Some(existing), Some(resource)) => Arc::ptr_eq(existing, resource),
+                (existing, resource) => existing.is_none() && resource.is_none(),
+            })
+            .unwrap_or_else(|| {
+                resources.push((resource, Vec::new()));
+                resources.len() - 1
+            });
+        let libraries = &mut resources[index].1;
+        match libraries
+            .iter_mut()
+            .find(|(library, _)| *library == &span.instrumentation_lib)
+        {
+            Some((_, spans)) => spans.push(span),
+            None => libraries.push((&span.instrumentation_lib, vec![span])),
+        }
+    }
+
+    let resource_spans: Vec<_> = resources
+        .into_iter()
+        .map(|(resource, libraries)| {
+            let scope_spans: Vec<_> = libraries
+                .into_iter()
+                .map(|(library, spans)| {
+                    json!({
+                        "scope": {
+                            "name": library.name,
+                            "version": library.version.as_deref().unwrap_or_default(),
+                        },
+                        "spans": spans.into_iter().map(otlp_span).collect::<Vec<_>>(),
+                    })
+                })
+                .collect();
+            json!({
+                "resource": {
+                    "attributes": resource
+                        .map(|resource| attributes(resource.iter()))
+                        .unwrap_or_default(),
+                },
+                "scopeSpans": scope_spans,
+            })
+        })
+        .collect();
+    json!({ "resourceSpans": resource_spans })
+}
+
+fn otlp_span(span: &SpanData) -> serde_json::Value {
+    let kind = match span.span_kind {
+        SpanKind::Internal => 1,
+        SpanKind::Server => 2,
+        SpanKind::Client => 3,
+        SpanKind::Producer => 4,
+        SpanKind::Consumer => 5,
+    };
+    let status = match span.status_code {
+        StatusCode::Unset => 0,
+        StatusCode::Ok => 1,
+        StatusCode::Error => 2,
+    };
+    let mut otlp = json!({
+        "traceId": trace_id(span.span_context.trace_id()),
+        "spanId": span_id(span.span_context.span_id()),
+        "traceState": span.span_context.trace_state().header(),
+        "name": span.name,
+        "kind": kind,
+        "startTimeUnixNano": unix_nanos(span.start_time),
+        "endTimeUnixNano": unix_nanos(span.end_time),
+        "attributes": attributes(span.attributes.iter()),
+        "events": span.events.iter().map(|event| json!({
+            "timeUnixNano": unix_nanos(event.timestamp),
+            "name": event.name,
+            "attributes": attributes(event.attributes.iter().map(|kv| (&kv.key, &kv.value))),
+        })).collect::<Vec<_>>(),
+        "links": span.links.iter().map(|link| json!({
+            "traceId": trace_id(link.span_context().trace_id()),
+            "spanId": span_id(link.span_context().span_id()),
+            "traceState": link.span_context().trace_state().header(),
+            "attributes": attributes(link.attributes().iter().map(|kv| (&kv.key, &kv.value))),
+        })).collect::<Vec<_>>(),
+        "status": { "code": status, "message": span.status_message },
+    });
+    if span.parent_span_id != SpanId::INVALID {
+        otlp["parentSpanId"] = span_id(span.parent_span_id).into();
+    }
+    otlp
+}
+
+fn attributes<'a>(
+    attributes: impl Iterator<Item = (&'a Key, &'a Value)>,
+) -> Vec<serde_json::Value> {
+    attributes
+        .map(|(key, value)| json!({ "key": key.as_str(), "value": any_value(value) }))
+        .collect()
+}
+
+/// The `AnyValue` of `value`, 64 bit integers are strings in the JSON encoding of protobuf.
+fn any_value(value: &Value) -> serde_json::Value {
+    let array = |values: Vec<serde_json::Value>| json!({ "arrayValue": { "values": values } });
+    match value {
+        Value::Bool(value) => json!({ "boolValue": value }),
+        Value::I64(value) => json!({ "intValue": value.to_string() }),
+        Value::F64(value) => json!({ "doubleValue": value }),
+        Value::String(value) => json!({ "stringValue": value.as_ref() }),
+        Value::Array(Array::Bool(values)) => array(
+            values
+                .iter()
+                .map(|value| any_value(&Value::Bool(*value)))
+                .collect(),
+        ),
+        Value::Array(Array::I64(values)) => array(
+            values
+                .iter()
+                .map(|value| any_value(&Value::I64(*value)))
+                .collect(),
+        ),
+        Value::Array(Array::F64(values)) => array(
+            values
+                .iter()
+                .map(|value| any_value(&Value::F64(*value)))
+                .collect(),
+        ),
+        Value::Array(Array::String(values)) => array(
+            values
+                .iter()
+                .map(|value| json!({ "stringValue": value.as_ref() }))
+                .collect(),
+        ),
+    }
+}
+
+fn trace_id(trace_id: TraceId) -> String {
+    format!("{:032x}", u128::from_be_bytes(trace_id.to_bytes()))
+}
+
+fn span_id(span_id: SpanId) -> String {
+    format!("{:016x}", u64::from_be_bytes(span_id.to_bytes()))
+}
+
+fn unix_nanos(time: SystemTime) -> String {
+    time.duration_since(SystemTime::UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_nanos())
+        .to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use opentelemetry::sdk::trace::{EvictedHashMap, EvictedQueue};
+    use opentelemetry::trace::{SpanContext, TraceFlags, TraceState};
+    use opentelemetry::KeyValue;
+    use std::borrow::Cow;
+
+    fn span(span_id: u64, parent_id: u64, resource: &Arc<Resource>) -> SpanData {
+        let mut attributes = EvictedHashMap::new(128, 0);
+        attributes.insert(KeyValue::new("http.status_code", 200_i64));
+        attributes.insert(KeyValue::new("cached", true));
+        SpanData {
+            span_context: SpanContext::new(
+                TraceId::from_u128(0xabc),
+                SpanId::from_u64(span_id),
+                TraceFlags::SAMPLED,
+                false,
+                TraceState::default(),
+            ),
+            parent_span_id: SpanId::from_u64(parent_id),
+            span_kind: SpanKind::Server,
+            name: Cow::Borrowed("request"),
+            start_time: SystemTime::UNIX_EPOCH + Duration::from_secs(1),
+            end_time: SystemTime::UNIX_EPOCH + Duration::from_secs(2),
+            attributes,
+            events: EvictedQueue::new(128),
+            links: EvictedQueue::new(128),
+            status_code: StatusCode::Error,
+            status_message: Cow::Borrowed("failed"),
+            resource: Some(Arc::clone(resource)),
+            instrumentation_lib: InstrumentationLibrary::new("worker", Some("1.0.0")),
+        }
+    }
+
+    #[test]
+    fn test_export_request() {
+        let resource = Arc::new(Resource::new([KeyValue::new("service.name", "api")]));
+        let request = export_request(&[span(1, 0, &resource), span(2, 1, &resource)]);
+
+        let resource_spans = request["resourceSpans"].as_array().unwrap();
+        assert_eq!(resource_spans.len(), 1);
+        assert_eq!(
+            resource_spans[0]["resource"]["attributes"],
+            json!([{ "key": "service.name", "value": { "stringValue": "api" } }])
+        );
+        let scope_spans = resource_spans[0]["scopeSpans"].as_array().unwrap();
+        assert_eq!(scope_spans.len(), 1);
+        assert_eq!(
+            scope_spans[0]["scope"],
+            json!({ "name": "worker", "version": "1.0.0" })
+        );
+
+        let spans = scope_spans[0]["spans"].as_array().unwrap();
+        assert_eq!(spans.len(), 2);
+        let root = &spans[0];
+        assert_eq!(root["traceId"], "00000000000000000000000000000abc");
+        assert_eq!(root["spanId"], "0000000000000001");
+        assert!(root.get("parentSpanId").is_none());
+        assert_eq!(root["kind"], 2);
+        assert_eq!(root["startTimeUnixNano"], "1000000000");
+        assert_eq!(root["endTimeUnixNano"], "2000000000");
+        assert_eq!(root["status"], json!({ "code": 2, "message": "failed" }));
+        let attributes = root["attributes"].as_array().unwrap();
+        assert!(attributes
+            .contains(&json!({ "key": "http.status_code", "value": { "intValue": "200" } })));
+        assert!(attributes.contains(&json!({ "key": "cached", "value": { "boolValue": true } })));
+        assert_eq!(spans[1]["parentSpanId"], "0000000000000001");
+    }
+
+    #[test]
+    fn test_export_request_groups_resources() {
+        let api = Arc::new(Resource::new([KeyValue::new("service.name", "api")]));
+        let auth = Arc::new(Resource::new([KeyValue::new("service.name", "auth")]));
+        let request = export_request(&[span(1, 0, &api), span(2, 0, &auth), span(3, 1, &api)]);
+
+        let span_counts: Vec<usize> = request["resourceSpans"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|resource| resource["scopeSpans"][0]["spans"].as_array().unwrap().len())
+            .collect();
+        assert_eq!(span_counts, vec![2, 1]);
+    }
+
+    #[test]
+    fn test_any_value() {
+        assert_eq!(
+            any_value(&Value::Array(Array::I64(vec![1, 2]))),
+            json!({ "arrayValue": { "values": [{ "intValue": "1" }, { "intValue": "2" }] } })
+        );
+        assert_eq!(any_value(&Value::F64(0.5)), json!({ "doubleValue": 0.5 }));
+    }
+}
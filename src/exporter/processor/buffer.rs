@@ -0,0 +1,429 @@
+use opentelemetry::sdk::export::trace::SpanData;
+use opentelemetry::trace::{SpanId, TraceId};
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+
+/// Spans waiting to be exported.
+#[derive(Debug, Default)]
+pub(super) struct SpanBuffer {
+    /// Spans ready to be exported.
+    pub(super) ready: Vec<SpanData>,
+    /// Spans of failed exports, exported first on the next flush.
+    retry: Vec<SpanData>,
+    /// Spans of traces whose local root span hasn't ended yet, when only complete traces are
+    /// exported.
+    pending: HashMap<TraceId, PendingTrace>,
+    /// Started local root spans, i.e. without a parent or with a remote one, and when they
+    /// started.
+    local_roots: HashMap<SpanId, SystemTime>,
+    /// Request of the spans started with a request id, until they are exported or dropped.
+    scopes: HashMap<SpanId, Arc<str>>,
+}
+
+#[derive(Debug)]
+struct PendingTrace {
+    spans: Vec<SpanData>,
+    since: SystemTime,
+}
+
+impl SpanBuffer {
+    /// Number of buffered spans, ready or not.
+    pub(super) fn len(&self) -> usize {
+        self.ready.len()
+            + self.retry.len()
+            + self
+                .pending
+                .values()
+                .map(|trace| trace.spans.len())
+                .sum::<usize>()
+    }
+
+    pub(super) fn start_local_root(&mut self, span_id: SpanId, now: SystemTime) {
+        self.local_roots.insert(span_id, now);
+    }
+
+    pub(super) fn start_scoped(&mut self, span_id: SpanId, scope: Arc<str>) {
+        self.scopes.insert(span_id, scope);
+    }
+
+    /// Keep `span` aside until the local root span of its trace ends.
+    ///
+    /// With `partial_flush_min_spans`, the finished spans of a trace are made ready as soon as
+    /// there are that many of them, even though the root span is still running.
+    pub(super) fn push_until_complete(
+        &mut self,
+        span: SpanData,
+        now: SystemTime,
+        partial_flush_min_spans: Option<usize>,
+    ) {
+        let trace_id = span.span_context.trace_id();
+        let is_local_root = self
+            .local_roots
+            .remove(&span.span_context.span_id())
+            .is_some();
+
+        let trace = self
+            .pending
+            .entry(trace_id)
+            .or_insert_with(|| PendingTrace {
+                spans: Vec::new(),
+                since: now,
+            });
+        trace.spans.push(span);
+
+        if is_local_root {
+            if let Some(trace) = self.pending.remove(&trace_id) {
+                self.ready.extend(trace.spans);
+            }
+        } else if partial_flush_min_spans.map_or(false, |min_spans| trace.spans.len() >= min_spans)
+        {
+            self.ready.append(&mut trace.spans);
+            trace.since = now;
+        }
+    }
+
+    /// Forget a span which won't be buffered, releasing its trace if it was the local root.
+    pub(super) fn discard(&mut self, span: &SpanData) {
+        self.scopes.remove(&span.span_context.span_id());
+        if self
+            .local_roots
+            .remove(&span.span_context.span_id())
+            .is_some()
+        {
+            if let Some(trace) = self.pending.remove(&span.span_context.trace_id()) {
+                self.ready.extend(trace.spans);
+            }
+        }
+    }
+
+    pub(super) fn is_local_root(&self, span_id: SpanId) -> bool {
+        self.local_roots.contains_key(&span_id)
+    }
+
+    /// Forget a local root span and the pending spans of its trace.
+    pub(super) fn drop_trace(&mut self, root: &SpanData) {
+        self.local_roots.remove(&root.span_context.span_id());
+        self.scopes.remove(&root.span_context.span_id());
+        if let Some(trace) = self.pending.remove(&root.span_context.trace_id()) {
+            for span in &trace.spans {
+                self.scopes.remove(&span.span_context.span_id());
+            }
+        }
+    }
+
+    /// Make the traces pending for longer than `max_age` ready, even though they are incomplete.
+    ///
+    /// The local roots started longer than `max_age` ago are forgotten, their trace would expire
+    /// anyway, so the ones which never end don't grow the buffer forever.
+    pub(super) fn promote_expired(&mut self, max_age: Duration, now: SystemTime) {
+        let is_expired = |since: SystemTime| {
+            now.duration_since(since)
+                .map_or(false, |age| age >= max_age)
+        };
+        self.local_roots.retain(|_, started| !is_expired(*started));
+
+        let expired: Vec<TraceId> = self
+            .pending
+            .iter()
+            .filter(|(_, trace)| is_expired(trace.since))
+            .map(|(trace_id, _)| *trace_id)
+            .collect();
+
+        for trace_id in expired {
+            if let Some(trace) = self.pending.remove(&trace_id) {
+                self.ready.extend(trace.spans);
+            }
+        }
+    }
+
+    /// Take the oldest ready spans, at most `max_spans` of them and, with `max_bytes`, as many as
+    /// fit in that estimated payload size. At least one span is taken if any is ready, so a
+    /// single oversized span can't block the buffer.
+    pub(super) fn drain_ready(
+        &mut self,
+        max_spans: usize,
+        max_bytes: Option<usize>,
+    ) -> Vec<SpanData> {
+        if !self.retry.is_empty() {
+            self.ready.splice(0..0, self.retry.drain(..));
+        }
+
+        let mut count = self.ready.len().min(max_spans);
+
+        if let Some(max_bytes) = max_bytes {
+            let mut batch_bytes = 0;
+            let fitting = self
+                .ready
+                .iter()
+                .take(count)
+                .take_while(|span| {
+                    batch_bytes += estimated_size(span);
+                    batch_bytes <= max_bytes
+                })
+                .count();
+            count = fitting.max(count.min(1));
+        }
+
+        self.ready.drain(0..count).collect()
+    }
+
+    /// Take at most `max_spans` of the spans of the request `scope`, those of failed exports
+    /// first. The request being over, its pending traces are made ready though incomplete.
+    pub(super) fn drain_scope(&mut self, scope: &str, max_spans: usize) -> Vec<SpanData> {
+        if !self.retry.is_empty() {
+            self.ready.splice(0..0, self.retry.drain(..));
+        }
+
+        let scopes = &self.scopes;
+        let in_scope = |span: &SpanData| {
+            scopes
+                .get(&span.span_context.span_id())
+                .map_or(false, |span_scope| &**span_scope == scope)
+        };
+        let scoped: Vec<TraceId> = self
+            .pending
+            .iter()
+            .filter(|(_, trace)| trace.spans.iter().any(in_scope))
+            .map(|(trace_id, _)| *trace_id)
+            .collect();
+        for trace_id in scoped {
+            if let Some(trace) = self.pending.remove(&trace_id) {
+                self.ready.extend(trace.spans);
+            }
+        }
+
+        let (mut batch, rest): (Vec<SpanData>, Vec<SpanData>) = std::mem::take(&mut self.ready)
+            .into_iter()
+            .partition(in_scope);
+
+        let mut overflow = batch.split_off(max_spans.min(batch.len()));
+        overflow.extend(rest);
+        self.ready = overflow;
+
+        batch
+    }
+
+    /// Forget the request of exported spans, unless they are kept to be retried.
+    pub(super) fn release_scopes(&mut self, span_ids: &[SpanId]) {
+        if self.scopes.is_empty() {
+            return;
+        }
+
+        let retried: HashSet<SpanId> = self
+            .retry
+            .iter()
+            .map(|span| span.span_context.span_id())
+            .collect();
+        for span_id in span_ids {
+            if !retried.contains(span_id) {
+                self.scopes.remove(span_id);
+            }
+        }
+    }
+
+    /// Keep the spans of a failed export to retry them, as long as there are less than
+    /// `max_spans` waiting for a retry. The spans which don't fit are left in `spans`.
+    pub(super) fn requeue(&mut self, spans: &mut Vec<SpanData>, max_spans: usize) {
+        let room = max_spans.saturating_sub(self.retry.len()).min(spans.len());
+        self.retry.extend(spans.drain(0..room));
+    }
+
+    /// Make every pending trace ready.
+    pub(super) fn promote_all(&mut self) {
+        for (_, trace) in self.pending.drain() {
+            self.ready.extend(trace.spans);
+        }
+    }
+}
+
+/// Rough size of a span once encoded, dominated by its strings.
+fn estimated_size(span: &SpanData) -> usize {
+    /// Ids, timestamps and the protobuf framing of a span.
+    const SPAN_OVERHEAD: usize = 64;
+    /// Framing of each map entry or event.
+    const ENTRY_OVERHEAD: usize = 4;
+
+    let attributes: usize = span
+        .attributes
+        .iter()
+        .map(|(key, value)| key.as_str().len() + value.as_str().len() + ENTRY_OVERHEAD)
+        .sum();
+    let events: usize = span
+        .events
+        .iter()
+        .map(|event| {
+            event.name.len()
+                + event
+                    .attributes
+                    .iter()
+                    .map(|kv| kv.key.as_str().len() + kv.value.as_str().len() + ENTRY_OVERHEAD)
+                    .sum::<usize>()
+        })
+        .sum();
+
+    SPAN_OVERHEAD + span.name.len() + attributes + events
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use opentelemetry::sdk::trace::{EvictedHashMap, EvictedQueue};
+    use opentelemetry::sdk::InstrumentationLibrary;
+    use opentelemetry::trace::{SpanContext, SpanKind, StatusCode, TraceFlags, TraceState};
+    use opentelemetry::KeyValue;
+    use std::borrow::Cow;
+
+    fn span(trace_id: u128, span_id: u64) -> SpanData {
+        SpanData {
+            span_context: SpanContext::new(
+                TraceId::from_u128(trace_id),
+                SpanId::from_u64(span_id),
+                TraceFlags::SAMPLED,
+                false,
+                TraceState::default(),
+            ),
+            parent_span_id: SpanId::INVALID,
+            span_kind: SpanKind::Internal,
+            name: Cow::Borrowed("span"),
+            start_time: SystemTime::UNIX_EPOCH,
+            end_time: SystemTime::UNIX_EPOCH,
+            attributes: EvictedHashMap::new(128, 0),
+            events: EvictedQueue::new(128),
+            links: EvictedQueue::new(128),
+            status_code: StatusCode::Unset,
+            status_message: Cow::Borrowed(""),
+            resource: None,
+            instrumentation_lib: InstrumentationLibrary::default(),
+        }
+    }
+
+    fn span_ids(spans: &[SpanData]) -> Vec<u64> {
+        spans
+            .iter()
+            .map(|span| u64::from_be_bytes(span.span_context.span_id().to_bytes()))
+            .collect()
+    }
+
+    #[test]
+    fn test_complete_trace() {
+        let now = SystemTime::UNIX_EPOCH;
+        let mut buffer = SpanBuffer::default();
+        buffer.start_local_root(SpanId::from_u64(1), now);
+
+        buffer.push_until_complete(span(1, 2), now, None);
+        buffer.push_until_complete(span(1, 3), now, None);
+        assert_eq!(buffer.len(), 2);
+        assert!(buffer.drain_ready(usize::MAX, None).is_empty());
+
+        buffer.push_until_complete(span(1, 1), now, None);
+        assert_eq!(
+            span_ids(&buffer.drain_ready(usize::MAX, None)),
+            vec![2, 3, 1]
+        );
+        assert_eq!(buffer.len(), 0);
+        assert!(!buffer.is_local_root(SpanId::from_u64(1)));
+    }
+
+    #[test]
+    fn test_partial_flush() {
+        let now = SystemTime::UNIX_EPOCH;
+        let mut buffer = SpanBuffer::default();
+        buffer.start_local_root(SpanId::from_u64(1), now);
+
+        buffer.push_until_complete(span(1, 2), now, Some(2));
+        assert!(buffer.drain_ready(usize::MAX, None).is_empty());
+        buffer.push_until_complete(span(1, 3), now, Some(2));
+        assert_eq!(span_ids(&buffer.drain_ready(usize::MAX, None)), vec![2, 3]);
+
+        buffer.push_until_complete(span(1, 1), now, Some(2));
+        assert_eq!(span_ids(&buffer.drain_ready(usize::MAX, None)), vec![1]);
+    }
+
+    #[test]
+    fn test_expired_trace() {
+        let max_age = Duration::from_secs(60);
+        let start = SystemTime::UNIX_EPOCH;
+        let mut buffer = SpanBuffer::default();
+        buffer.start_local_root(SpanId::from_u64(1), start);
+        buffer.push_until_complete(span(1, 2), start, None);
+
+        buffer.promote_expired(max_age, start + Duration::from_secs(59));
+        assert!(buffer.drain_ready(usize::MAX, None).is_empty());
+        assert!(buffer.is_local_root(SpanId::from_u64(1)));
+
+        buffer.promote_expired(max_age, start + max_age);
+        assert_eq!(span_ids(&buffer.drain_ready(usize::MAX, None)), vec![2]);
+        // The root which never ended is forgotten with its trace.
+        assert!(!buffer.is_local_root(SpanId::from_u64(1)));
+        assert!(buffer.local_roots.is_empty());
+    }
+
+    #[test]
+    fn test_drain_ready_bytes() {
+        let mut large = span(1, 1);
+        large
+            .attributes
+            .insert(KeyValue::new("db.statement", "x".repeat(1000)));
+        let mut buffer = SpanBuffer::default();
+        buffer.ready.extend([large, span(1, 2), span(1, 3)]);
+
+        // An oversized span is still taken on its own.
+        assert_eq!(
+            span_ids(&buffer.drain_ready(usize::MAX, Some(100))),
+            vec![1]
+        );
+        assert_eq!(
+            span_ids(&buffer.drain_ready(usize::MAX, Some(200))),
+            vec![2, 3]
+        );
+
+        buffer.ready.extend([span(1, 4), span(1, 5)]);
+        assert_eq!(span_ids(&buffer.drain_ready(1, None)), vec![4]);
+    }
+
+    #[test]
+    fn test_requeue() {
+        let mut buffer = SpanBuffer::default();
+        buffer.ready.push(span(1, 3));
+
+        let mut failed = vec![span(1, 1), span(1, 2)];
+        buffer.requeue(&mut failed, 1);
+        assert_eq!(span_ids(&failed), vec![2]);
+        assert_eq!(buffer.len(), 2);
+
+        // The retried spans go first.
+        assert_eq!(span_ids(&buffer.drain_ready(usize::MAX, None)), vec![1, 3]);
+    }
+
+    #[test]
+    fn test_drain_scope() {
+        let now = SystemTime::UNIX_EPOCH;
+        let request: Arc<str> = Arc::from("request");
+        let mut buffer = SpanBuffer::default();
+        for span_id in [1, 2, 3, 4] {
+            buffer.start_scoped(SpanId::from_u64(span_id), Arc::clone(&request));
+        }
+        buffer.start_scoped(SpanId::from_u64(10), Arc::from("other"));
+        buffer.start_local_root(SpanId::from_u64(4), now);
+
+        buffer.ready.extend([span(1, 10), span(1, 1)]);
+        let mut failed = vec![span(1, 2)];
+        buffer.requeue(&mut failed, 10);
+        // Pending until its root ends, which the end of the request doesn't wait for.
+        buffer.push_until_complete(span(2, 3), now, None);
+
+        let batch = buffer.drain_scope("request", 2);
+        assert_eq!(span_ids(&batch), vec![2, 1]);
+        assert_eq!(span_ids(&buffer.drain_scope("request", 2)), vec![3]);
+        assert!(buffer.drain_scope("request", 2).is_empty());
+        assert_eq!(span_ids(&buffer.ready), vec![10]);
+
+        // A requeued span keeps its request until it is exported.
+        let mut failed = batch;
+        buffer.requeue(&mut failed, 1);
+        buffer.release_scopes(&[SpanId::from_u64(2), SpanId::from_u64(1)]);
+        assert_eq!(span_ids(&buffer.drain_scope("request", 2)), vec![2]);
+        assert!(!buffer.scopes.contains_key(&SpanId::from_u64(1)));
+    }
+}
@@ -0,0 +1,55 @@
+use opentelemetry::trace::TraceId;
+use std::collections::HashSet;
+use std::sync::{Arc, Mutex, PoisonError};
+
+/// Traces remembered at most, the set is cleared past this so the traces whose remaining spans
+/// are never exported don't grow it forever.
+const MAX_DROPPED_TRACES: usize = 4096;
+
+/// Traces some spans of which were dropped by the processor, e.g. by the sampling or because its
+/// buffer was full, so the exporter flags their chunks with `dropped_trace`.
+///
+/// Clones share the same set, the processor records into the one of its exporter.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct DroppedTraces(Arc<Mutex<HashSet<TraceId>>>);
+
+impl DroppedTraces {
+    pub(crate) fn record(&self, trace_ids: impl IntoIterator<Item = TraceId>) {
+        let mut dropped = self.0.lock().unwrap_or_else(PoisonError::into_inner);
+        for trace_id in trace_ids {
+            if dropped.len() >= MAX_DROPPED_TRACES {
+                dropped.clear();
+            }
+            dropped.insert(trace_id);
+        }
+    }
+
+    /// Whether spans of the trace were dropped. The trace is kept so the spans of later batches
+    /// are flagged too, until it is forgotten.
+    pub(crate) fn contains(&self, trace_id: TraceId) -> bool {
+        self.0
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .contains(&trace_id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_dropped_traces() {
+        let dropped = DroppedTraces::default();
+        let shared = dropped.clone();
+        shared.record([TraceId::from_u128(1), TraceId::from_u128(2)]);
+
+        assert!(dropped.contains(TraceId::from_u128(1)));
+        assert!(dropped.contains(TraceId::from_u128(2)));
+        assert!(!dropped.contains(TraceId::from_u128(3)));
+
+        dropped.record((10..10 + MAX_DROPPED_TRACES as u128).map(TraceId::from_u128));
+        assert!(!dropped.contains(TraceId::from_u128(1)));
+        assert!(dropped.contains(TraceId::from_u128(10 + MAX_DROPPED_TRACES as u128 - 1)));
+    }
+}
@@ -0,0 +1,44 @@
+use super::WASMWorkerSpanProcessor;
+
+/// Flushes the [`WASMWorkerSpanProcessor`] when dropped, so spans are exported even when a handler
+/// returns early or fails.
+///
+/// On Workers dropping the guard spawns the flush on the local executor, which may be cancelled
+/// once the response is sent. Prefer [`FlushGuard::finish`] with the request context, which
+/// keeps the Worker alive until the flush completes. Outside of Workers there is no executor to
+/// spawn on and dropping the guard doesn't flush.
+#[derive(Debug)]
+#[must_use = "the processor is flushed when the guard is dropped"]
+pub struct FlushGuard {
+    processor: Option<WASMWorkerSpanProcessor>,
+}
+
+impl FlushGuard {
+    pub(super) fn new(processor: WASMWorkerSpanProcessor) -> Self {
+        FlushGuard {
+            processor: Some(processor),
+        }
+    }
+
+    /// Flush through `worker::Context::wait_until`, after the response is sent.
+    #[cfg(feature = "worker")]
+    pub fn finish(mut self, ctx: &worker::Context) {
+        if let Some(processor) = self.processor.take() {
+            ctx.wait_until(processor.flush_in_background());
+        }
+    }
+}
+
+impl Drop for FlushGuard {
+    #[cfg(target_arch = "wasm32")]
+    fn drop(&mut self) {
+        if let Some(processor) = self.processor.take() {
+            wasm_bindgen_futures::spawn_local(processor.flush_in_background());
+        }
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    fn drop(&mut self) {
+        self.processor.take();
+    }
+}
@@ -0,0 +1,1411 @@
+use async_trait::async_trait;
+use futures_util::future::{join, join_all};
+use futures_util::lock::Mutex;
+use itertools::Itertools;
+use opentelemetry::global;
+use opentelemetry::sdk::export::trace::{SpanData, SpanExporter};
+use opentelemetry::sdk::trace::{Span, SpanProcessor};
+use opentelemetry::trace::{Span as _, TraceContextExt, TraceError, TraceId, TraceResult};
+use std::any::Any;
+use std::cell::RefCell;
+use std::fmt;
+use std::future::Future;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+
+use super::{time, DatadogExporter, ExportInfo, RateLimit};
+use buffer::SpanBuffer;
+pub(crate) use dropped::DroppedTraces;
+pub use guard::FlushGuard;
+pub(crate) use sampler::TraceSampling;
+pub use scope::with_request_id;
+use scope::{RequestScope, ScopeRelease};
+
+mod buffer;
+mod dropped;
+mod guard;
+mod sampler;
+mod scope;
+
+thread_local! {
+    static SPANS: RefCell<SpanBuffer> = RefCell::new(SpanBuffer::default());
+}
+
+/// Run `f` on the span buffer.
+///
+/// The buffer is a thread local `RefCell`, which can't be poisoned by a panicking handler and
+/// doesn't contend with other isolates. It is only borrowed for the duration of `f`, so it should
+/// always be available given the single threaded runtime; if it somehow isn't, `None` is returned
+/// instead of panicking and breaking tracing for the rest of the isolate.
+fn with_buffer<R>(f: impl FnOnce(&mut SpanBuffer) -> R) -> Option<R> {
+    SPANS.with(|spans| spans.try_borrow_mut().ok().map(|mut spans| f(&mut spans)))
+}
+
+/// A [`SpanProcessor`] that exports asynchronously when asked to do it.
+///
+/// Clones share the same exporter and counters, so a clone can be moved into a `'static`
+/// future, see [`WASMWorkerSpanProcessor::flush_in_background`].
+#[derive(Clone, Debug)]
+#[allow(clippy::type_complexity)]
+pub struct WASMWorkerSpanProcessor {
+    inner: Arc<Inner>,
+}
+
+#[derive(Debug)]
+struct Inner {
+    exporter: DatadogExporter,
+    config: ProcessorConfig,
+    counters: Counters,
+    /// Set while an automatic flush is spawned, so only one runs at a time.
+    auto_flush_scheduled: AtomicBool,
+    /// Set while the [`FlushScheduler`] is expected to call back, so it is only asked once.
+    periodic_flush_scheduled: AtomicBool,
+    /// Set on shutdown, spans ending afterwards are dropped.
+    closed: AtomicBool,
+}
+
+/// Drives periodic flushes for long lived workloads where flushing at the end of each request
+/// doesn't apply, e.g. Durable Objects holding WebSockets.
+///
+/// Implementations arrange for [`WASMWorkerSpanProcessor::scheduled_flush`] to be called once
+/// `delay` elapsed, typically by setting a Durable Object alarm or relying on a cron trigger.
+/// The processor only asks for a new flush when spans are buffered and none is scheduled yet.
+pub trait FlushScheduler: Send + Sync {
+    /// Schedule a call to [`WASMWorkerSpanProcessor::scheduled_flush`] in `delay`.
+    fn schedule(&self, delay: Duration);
+}
+
+/// A [`FlushScheduler`] and the interval between two flushes, see
+/// [`DatadogPipelineBuilder::with_flush_scheduler`](super::DatadogPipelineBuilder::with_flush_scheduler).
+#[derive(Clone)]
+pub(crate) struct PeriodicFlush {
+    pub(crate) scheduler: Arc<dyn FlushScheduler>,
+    pub(crate) interval: Duration,
+}
+
+impl fmt::Debug for PeriodicFlush {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("PeriodicFlush")
+            .field("interval", &self.interval)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Predicate deciding whether a finished span is kept, see
+/// [`DatadogPipelineBuilder::with_span_filter`](super::DatadogPipelineBuilder::with_span_filter).
+#[derive(Clone)]
+pub(crate) struct SpanFilter(pub(crate) Arc<dyn Fn(&SpanData) -> bool + Send + Sync>);
+
+impl fmt::Debug for SpanFilter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("SpanFilter")
+    }
+}
+
+/// Hook rewriting every span right before it is exported, see
+/// [`DatadogPipelineBuilder::with_on_export`](super::DatadogPipelineBuilder::with_on_export).
+#[derive(Clone)]
+pub(crate) struct SpanMutator(pub(crate) Arc<dyn Fn(&mut SpanData) + Send + Sync>);
+
+impl fmt::Debug for SpanMutator {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("SpanMutator")
+    }
+}
+
+/// Callback receiving the batches Datadog rejected, see
+/// [`DatadogPipelineBuilder::with_export_error_handler`](super::DatadogPipelineBuilder::with_export_error_handler).
+#[derive(Clone)]
+pub(crate) struct ExportErrorHandler(
+    pub(crate) Arc<dyn Fn(&[SpanData], &TraceError) + Send + Sync>,
+);
+
+impl fmt::Debug for ExportErrorHandler {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("ExportErrorHandler")
+    }
+}
+
+/// Exporter receiving a copy of every batch next to Datadog, see
+/// [`DatadogPipelineBuilder::with_additional_exporter`](super::DatadogPipelineBuilder::with_additional_exporter),
+/// or the batches it rejected, see
+/// [`DatadogPipelineBuilder::with_fallback_exporter`](super::DatadogPipelineBuilder::with_fallback_exporter).
+#[derive(Clone, Debug)]
+pub(crate) struct AdditionalExporter(pub(crate) Arc<Mutex<Box<dyn SpanExporter>>>);
+
+/// Options of the [`WASMWorkerSpanProcessor`], set through the pipeline builder.
+#[derive(Clone, Debug)]
+pub(crate) struct ProcessorConfig {
+    pub(crate) flush_size: usize,
+    /// Estimated payload size above which a batch is cut, whatever its span count.
+    pub(crate) max_batch_bytes: Option<usize>,
+    pub(crate) auto_flush: bool,
+    pub(crate) max_in_flight_exports: usize,
+    /// Only export traces once their local root span ended, or after this age.
+    pub(crate) complete_traces_max_age: Option<Duration>,
+    /// Export the finished spans of an incomplete trace once there are that many.
+    pub(crate) partial_flush_min_spans: Option<usize>,
+    pub(crate) span_filter: Option<SpanFilter>,
+    pub(crate) on_export: Option<SpanMutator>,
+    pub(crate) additional_exporters: Vec<AdditionalExporter>,
+    /// Receives the batches Datadog rejected with a non retryable error.
+    pub(crate) fallback_exporter: Option<AdditionalExporter>,
+    pub(crate) export_error_handler: Option<ExportErrorHandler>,
+    /// Maximum number of spans of failed exports kept to be retried on the next flush.
+    pub(crate) retry_buffer_size: Option<usize>,
+    pub(crate) periodic_flush: Option<PeriodicFlush>,
+    pub(crate) trace_sampling: TraceSampling,
+}
+
+#[derive(Debug, Default)]
+struct Counters {
+    exported_spans: AtomicU64,
+    dropped_spans: AtomicU64,
+    export_failures: AtomicU64,
+    /// Unix time in milliseconds, `0` when nothing was exported yet.
+    last_export: AtomicU64,
+}
+
+/// A snapshot of the [`WASMWorkerSpanProcessor`] counters, see [`WASMWorkerSpanProcessor::stats`].
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ProcessorStats {
+    /// Spans waiting in the buffer for the next flush.
+    pub buffered_spans: usize,
+    /// Spans successfully sent to Datadog.
+    pub exported_spans: u64,
+    /// Spans lost, e.g. because the export carrying them failed.
+    pub dropped_spans: u64,
+    /// Number of failed exports.
+    pub export_failures: u64,
+    /// When the last successful export finished.
+    pub last_export: Option<SystemTime>,
+}
+
+/// Outcome of a flush which may have stopped before the buffer was empty.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct FlushSummary {
+    /// Spans successfully sent to Datadog during this flush.
+    pub exported_spans: usize,
+    /// Spans still buffered after this flush.
+    pub remaining_spans: usize,
+    /// Size of the request bodies successfully sent to Datadog.
+    pub bytes_sent: usize,
+    /// How long the flush took.
+    pub duration: Duration,
+    /// Whether the flush was interrupted by its deadline.
+    pub timed_out: bool,
+    /// Rate limit given by the intake in its last response, see [`ExportInfo::rate_limit`].
+    pub rate_limit: Option<RateLimit>,
+}
+
+impl WASMWorkerSpanProcessor {
+    pub(crate) fn new(exporter: DatadogExporter, config: ProcessorConfig) -> Self {
+        WASMWorkerSpanProcessor {
+            inner: Arc::new(Inner {
+                exporter,
+                config,
+                counters: Counters::default(),
+                auto_flush_scheduled: AtomicBool::new(false),
+                periodic_flush_scheduled: AtomicBool::new(false),
+                closed: AtomicBool::new(false),
+            }),
+        }
+    }
+
+    /// Whether at least `flush_size` spans are waiting in the buffer.
+    ///
+    /// With auto flush enabled the processor spawns the export itself on Workers, other
+    /// runtimes have no executor to spawn on and can poll this to decide when to flush.
+    #[must_use]
+    pub fn needs_flush(&self) -> bool {
+        self.ready_spans() >= self.inner.config.flush_size
+    }
+
+    /// Spawn the export of one batch unless one is already running.
+    #[cfg(target_arch = "wasm32")]
+    fn schedule_flush(&self) {
+        if self.inner.auto_flush_scheduled.swap(true, Ordering::AcqRel) {
+            return;
+        }
+
+        let processor = self.clone();
+        wasm_bindgen_futures::spawn_local(async move {
+            let result = SpanProcessExt::force_flush(&processor).await;
+            processor
+                .inner
+                .auto_flush_scheduled
+                .store(false, Ordering::Release);
+            if let Err(err) = result {
+                global::handle_error(err);
+            }
+        });
+    }
+
+    /// There is no executor to spawn on outside of Workers, see [`Self::needs_flush`].
+    #[cfg(not(target_arch = "wasm32"))]
+    #[allow(clippy::unused_self)]
+    fn schedule_flush(&self) {}
+
+    /// Ask the [`FlushScheduler`] for a flush unless one is already scheduled.
+    fn schedule_periodic_flush(&self) {
+        if let Some(periodic_flush) = &self.inner.config.periodic_flush {
+            if !self
+                .inner
+                .periodic_flush_scheduled
+                .swap(true, Ordering::AcqRel)
+            {
+                periodic_flush.scheduler.schedule(periodic_flush.interval);
+            }
+        }
+    }
+
+    /// Export every buffered span, to be called by the [`FlushScheduler`] when the flush it
+    /// scheduled is due.
+    ///
+    /// Another flush is scheduled if spans are still buffered afterwards, e.g. because the export
+    /// failed or spans ended in the meantime.
+    ///
+    /// # Errors
+    ///
+    /// If the export of a batch failed.
+    pub async fn scheduled_flush(&self) -> TraceResult<()> {
+        self.inner
+            .periodic_flush_scheduled
+            .store(false, Ordering::Release);
+
+        let result = self.force_flush_all().await;
+        if self.buffered_spans() > 0 {
+            self.schedule_periodic_flush();
+        }
+
+        result
+    }
+
+    /// A `'static` future exporting every buffered span, meant to be handed to
+    /// `worker::Context::wait_until` so the flush happens after the response is sent.
+    ///
+    /// Export errors are reported through `opentelemetry::global::handle_error`.
+    pub fn flush_in_background(&self) -> impl Future<Output = ()> + Send + 'static {
+        let processor = self.clone();
+
+        async move {
+            if let Err(err) = processor.force_flush_all().await {
+                global::handle_error(err);
+            }
+        }
+    }
+
+    /// Export the ready spans started in a context marked with [`with_request_id`] for
+    /// `request_id`, in batches of `flush_size`, leaving the spans of other requests buffered.
+    ///
+    /// # Errors
+    ///
+    /// If the export of a batch failed, the spans of the request that were not sent yet stay
+    /// buffered.
+    pub async fn flush_request(&self, request_id: &str) -> TraceResult<FlushSummary> {
+        let start = time::now();
+        let mut summary = FlushSummary::default();
+
+        loop {
+            let to_export = with_buffer(|buffer| {
+                if let Some(max_age) = self.inner.config.complete_traces_max_age {
+                    buffer.promote_expired(max_age, time::now());
+                }
+                buffer.drain_scope(request_id, self.inner.config.flush_size)
+            })
+            .unwrap_or_default();
+            if to_export.is_empty() {
+                break;
+            }
+
+            let span_count = to_export.len();
+            summary.bytes_sent += self.export_batch(to_export).await?;
+            summary.exported_spans += span_count;
+            summary.rate_limit = self.last_rate_limit();
+        }
+
+        summary.remaining_spans = self.buffered_spans();
+        summary.duration = time::now().duration_since(start).unwrap_or_default();
+        Ok(summary)
+    }
+
+    /// A guard flushing every buffered span when dropped, to be created at the start of a
+    /// request handler, see [`FlushGuard`].
+    pub fn flush_guard(&self) -> FlushGuard {
+        FlushGuard::new(self.clone())
+    }
+
+    /// What happened to the last export of the Datadog exporter, see
+    /// [`DatadogExporter::last_export_info`].
+    #[must_use]
+    pub fn last_export_info(&self) -> Option<ExportInfo> {
+        self.inner.exporter.last_export_info()
+    }
+
+    fn last_rate_limit(&self) -> Option<RateLimit> {
+        self.last_export_info().and_then(|info| info.rate_limit)
+    }
+
+    /// Counters about the spans that went through this processor, to monitor trace loss.
+    #[must_use]
+    pub fn stats(&self) -> ProcessorStats {
+        let last_export = self.inner.counters.last_export.load(Ordering::Relaxed);
+
+        ProcessorStats {
+            buffered_spans: self.buffered_spans(),
+            exported_spans: self.inner.counters.exported_spans.load(Ordering::Relaxed),
+            dropped_spans: self.inner.counters.dropped_spans.load(Ordering::Relaxed),
+            export_failures: self.inner.counters.export_failures.load(Ordering::Relaxed),
+            last_export: (last_export != 0).then(|| time::from_unix_millis(last_export)),
+        }
+    }
+
+    /// Take at most `flush_size` spans, and `max_batch_bytes` of them, out of the buffer.
+    fn drain_batch(&self) -> Vec<SpanData> {
+        with_buffer(|buffer| {
+            if let Some(max_age) = self.inner.config.complete_traces_max_age {
+                buffer.promote_expired(max_age, time::now());
+            }
+
+            buffer.drain_ready(
+                self.inner.config.flush_size,
+                self.inner.config.max_batch_bytes,
+            )
+        })
+        .unwrap_or_default()
+    }
+
+    fn record_dropped(&self, span_count: u64) {
+        self.inner
+            .counters
+            .dropped_spans
+            .fetch_add(span_count, Ordering::Relaxed);
+    }
+
+    /// Flag the chunks of these traces as partially dropped in the next exports.
+    fn record_dropped_traces(&self, trace_ids: impl IntoIterator<Item = TraceId>) {
+        self.inner.exporter.dropped_traces.record(trace_ids);
+    }
+
+    fn buffered_spans(&self) -> usize {
+        with_buffer(|buffer| buffer.len()).unwrap_or_default()
+    }
+
+    fn ready_spans(&self) -> usize {
+        with_buffer(|buffer| buffer.ready.len()).unwrap_or_default()
+    }
+
+    /// Apply the head sampling, forgetting the traces which are not kept.
+    fn sample(&self, span: &SpanData) -> bool {
+        let sampling = &self.inner.config.trace_sampling;
+
+        if let Some(rate) = sampling.rate {
+            if !sampler::keep_trace(span.span_context.trace_id(), rate) {
+                with_buffer(|buffer| buffer.discard(span));
+                return false;
+            }
+        }
+
+        if let Some(root_sampler) = &sampling.root_sampler {
+            let span_id = span.span_context.span_id();
+            if with_buffer(|buffer| buffer.is_local_root(span_id)).unwrap_or(false)
+                && !root_sampler(span)
+            {
+                with_buffer(|buffer| buffer.drop_trace(span));
+                self.record_dropped_traces([span.span_context.trace_id()]);
+                return false;
+            }
+        }
+
+        true
+    }
+
+    /// Export a batch, keeping the counters up to date.
+    ///
+    /// Resolves to the number of bytes sent to Datadog.
+    async fn export_batch(&self, mut batch: Vec<SpanData>) -> TraceResult<usize> {
+        if let Some(SpanMutator(on_export)) = &self.inner.config.on_export {
+            batch.iter_mut().for_each(|span| on_export(span));
+        }
+
+        let span_count = batch.len() as u64;
+        let _release = ScopeRelease(
+            batch
+                .iter()
+                .map(|span| span.span_context.span_id())
+                .collect(),
+        );
+        let trace_ids: Vec<TraceId> = batch
+            .iter()
+            .map(|span| span.span_context.trace_id())
+            .unique()
+            .collect();
+        let kept_batch = (self.inner.config.export_error_handler.is_some()
+            || self.inner.config.retry_buffer_size.is_some()
+            || self.inner.config.fallback_exporter.is_some())
+        .then(|| batch.clone());
+        let additional_exports: Vec<_> = self
+            .inner
+            .config
+            .additional_exporters
+            .iter()
+            .map(|AdditionalExporter(exporter)| {
+                let batch = batch.clone();
+                async move { exporter.lock().await.export(batch).await }
+            })
+            .collect();
+        let (result, additional_results) = join(
+            self.inner.exporter.export(batch),
+            join_all(additional_exports),
+        )
+        .await;
+
+        // The additional exporters don't affect the counters nor the result, a failure of one of
+        // them shouldn't lose the spans sent to Datadog.
+        additional_results
+            .into_iter()
+            .filter_map(Result::err)
+            .for_each(global::handle_error);
+
+        let retryable = matches!(&result, Err(err) if err.is_retryable());
+        let result = result.map_err(TraceError::from);
+
+        match &result {
+            Ok(_) => {
+                self.inner
+                    .counters
+                    .exported_spans
+                    .fetch_add(span_count, Ordering::Relaxed);
+                self.inner
+                    .counters
+                    .last_export
+                    .store(time::to_unix_millis(time::now()), Ordering::Relaxed);
+            }
+            Err(err) => {
+                let mut failed = kept_batch;
+                if let (true, Some(failed), Some(retry_buffer_size)) =
+                    (retryable, &mut failed, self.inner.config.retry_buffer_size)
+                {
+                    with_buffer(|buffer| buffer.requeue(failed, retry_buffer_size));
+                }
+                if let (false, Some(AdditionalExporter(fallback)), Some(failed)) =
+                    (retryable, &self.inner.config.fallback_exporter, &mut failed)
+                {
+                    if !failed.is_empty() {
+                        match fallback.lock().await.export(failed.clone()).await {
+                            Ok(()) => failed.clear(),
+                            Err(err) => global::handle_error(err),
+                        }
+                    }
+                }
+                let dropped = failed
+                    .as_ref()
+                    .map_or(span_count, |failed| failed.len() as u64);
+                match &failed {
+                    Some(failed) => self.record_dropped_traces(
+                        failed.iter().map(|span| span.span_context.trace_id()),
+                    ),
+                    None => self.record_dropped_traces(trace_ids),
+                }
+
+                if let (Some(ExportErrorHandler(handler)), Some(failed)) =
+                    (&self.inner.config.export_error_handler, &failed)
+                {
+                    if !failed.is_empty() {
+                        handler(failed, err);
+                    }
+                }
+                self.record_dropped(dropped);
+                self.inner
+                    .counters
+                    .export_failures
+                    .fetch_add(1, Ordering::Relaxed);
+            }
+        }
+
+        result
+    }
+}
+
+#[async_trait]
+pub trait SpanProcessExt {
+    /// Export at most `flush_size` of the buffered spans.
+    ///
+    /// The summary tells whether spans remain buffered, e.g. to schedule another flush.
+    async fn force_flush(&self) -> TraceResult<FlushSummary>;
+
+    /// Export every buffered span, in batches of `flush_size`.
+    ///
+    /// Up to `max_in_flight_exports` batches are sent concurrently. Stops after a round with a
+    /// failed export, the spans that were not sent yet stay buffered.
+    async fn force_flush_all(&self) -> TraceResult<()>;
+
+    /// Export the buffered spans in batches of `flush_size` until the buffer is empty or
+    /// `timeout` is reached, e.g. to stay within the `waitUntil` budget of a Worker.
+    ///
+    /// The batch in flight when the deadline is reached is abandoned and counted as dropped.
+    async fn force_flush_with_timeout(&self, timeout: Duration) -> TraceResult<FlushSummary>;
+
+    /// Close the processor and export every buffered span.
+    ///
+    /// Spans ending after the shutdown are counted as dropped instead of being buffered.
+    async fn shutdown(&self) -> TraceResult<()>;
+}
+
+#[async_trait]
+impl SpanProcessExt for WASMWorkerSpanProcessor {
+    async fn force_flush(&self) -> TraceResult<FlushSummary> {
+        let start = time::now();
+        let to_export = self.drain_batch();
+        let exported_spans = to_export.len();
+        let bytes_sent = self.export_batch(to_export).await?;
+
+        Ok(FlushSummary {
+            exported_spans,
+            remaining_spans: self.buffered_spans(),
+            bytes_sent,
+            duration: time::now().duration_since(start).unwrap_or_default(),
+            timed_out: false,
+            rate_limit: self.last_rate_limit(),
+        })
+    }
+
+    async fn force_flush_all(&self) -> TraceResult<()> {
+        let max_in_flight = self.inner.config.max_in_flight_exports.max(1);
+
+        loop {
+            let batches: Vec<Vec<SpanData>> = (0..max_in_flight)
+                .map(|_| self.drain_batch())
+                .take_while(|batch| !batch.is_empty())
+                .collect();
+            if batches.is_empty() {
+                return Ok(());
+            }
+
+            join_all(batches.into_iter().map(|batch| self.export_batch(batch)))
+                .await
+                .into_iter()
+                .collect::<TraceResult<Vec<usize>>>()?;
+        }
+    }
+
+    async fn force_flush_with_timeout(&self, timeout: Duration) -> TraceResult<FlushSummary> {
+        let start = time::now();
+        let deadline = start + timeout;
+        let mut summary = FlushSummary::default();
+
+        loop {
+            let remaining = deadline
+                .duration_since(time::now())
+                .unwrap_or(Duration::ZERO);
+            if remaining.is_zero() {
+                summary.timed_out = true;
+                break;
+            }
+
+            let to_export = self.drain_batch();
+            if to_export.is_empty() {
+                break;
+            }
+
+            let span_count = to_export.len();
+            let trace_ids: Vec<TraceId> = to_export
+                .iter()
+                .map(|span| span.span_context.trace_id())
+                .unique()
+                .collect();
+            match time::timeout(remaining, self.export_batch(to_export)).await {
+                Ok(result) => {
+                    summary.bytes_sent += result?;
+                    summary.exported_spans += span_count;
+                    summary.rate_limit = self.last_rate_limit();
+                }
+                Err(time::Elapsed) => {
+                    self.record_dropped(span_count as u64);
+                    self.record_dropped_traces(trace_ids);
+                    self.inner
+                        .counters
+                        .export_failures
+                        .fetch_add(1, Ordering::Relaxed);
+                    summary.timed_out = true;
+                    break;
+                }
+            }
+        }
+
+        summary.remaining_spans = self.buffered_spans();
+        summary.duration = time::now().duration_since(start).unwrap_or_default();
+        Ok(summary)
+    }
+
+    async fn shutdown(&self) -> TraceResult<()> {
+        self.inner.closed.store(true, Ordering::Release);
+        with_buffer(SpanBuffer::promote_all);
+
+        let result = self.force_flush_all().await;
+        for AdditionalExporter(exporter) in self
+            .inner
+            .config
+            .additional_exporters
+            .iter()
+            .chain(&self.inner.config.fallback_exporter)
+        {
+            exporter.lock().await.shutdown();
+        }
+
+        result
+    }
+}
+
+impl SpanProcessor for WASMWorkerSpanProcessor {
+    fn on_start(&self, span: &mut Span, cx: &opentelemetry::Context) {
+        if let Some(RequestScope(scope)) = cx.get::<RequestScope>() {
+            let span_id = span.span_context().span_id();
+            with_buffer(|buffer| buffer.start_scoped(span_id, scope.clone()));
+        }
+
+        if self.inner.config.complete_traces_max_age.is_none() {
+            return;
+        }
+
+        let parent = cx.span();
+        let parent = parent.span_context();
+        if !parent.is_valid() || parent.is_remote() {
+            let span_id = span.span_context().span_id();
+            with_buffer(|buffer| buffer.start_local_root(span_id, time::now()));
+        }
+    }
+
+    fn on_end(&self, span: SpanData) {
+        if self.inner.closed.load(Ordering::Acquire) {
+            self.record_dropped(1);
+            self.record_dropped_traces([span.span_context.trace_id()]);
+            return;
+        }
+
+        if !self.sample(&span) {
+            return;
+        }
+
+        if let Some(SpanFilter(filter)) = &self.inner.config.span_filter {
+            if !filter(&span) {
+                with_buffer(|buffer| buffer.discard(&span));
+                return;
+            }
+        }
+
+        let trace_id = span.span_context.trace_id();
+        let pushed = with_buffer(|buffer| {
+            if self.inner.config.complete_traces_max_age.is_some() {
+                buffer.push_until_complete(
+                    span,
+                    time::now(),
+                    self.inner.config.partial_flush_min_spans,
+                );
+            } else {
+                buffer.ready.push(span);
+            }
+        });
+        if pushed.is_none() {
+            self.record_dropped(1);
+            self.record_dropped_traces([trace_id]);
+            return;
+        }
+
+        if self.inner.config.auto_flush && self.needs_flush() {
+            self.schedule_flush();
+        }
+        self.schedule_periodic_flush();
+    }
+
+    fn force_flush(&self) -> TraceResult<()> {
+        Err(TraceError::from(
+            "Sync flush is not supported, use `force_flush` from `SpanProcessExt`",
+        ))
+    }
+
+    fn shutdown(&mut self) -> TraceResult<()> {
+        // Exporting is async, so the sync shutdown can only close the processor and drop the
+        // spans still buffered, e.g. when the tracer provider is dropped. They are exported by
+        // calling the `shutdown` from `SpanProcessExt` first.
+        self.inner.closed.store(true, Ordering::Release);
+
+        let discarded = with_buffer(|buffer| {
+            buffer.promote_all();
+            let spans = buffer.drain_ready(usize::MAX, None);
+            *buffer = SpanBuffer::default();
+            spans
+        })
+        .unwrap_or_default();
+        if !discarded.is_empty() {
+            self.record_dropped(discarded.len() as u64);
+        }
+
+        Ok(())
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dd_proto;
+    use crate::exporter::{new_pipeline, DatadogPipelineBuilder, OtlpHttpExporter};
+    use opentelemetry::sdk::trace::{EvictedHashMap, EvictedQueue};
+    use opentelemetry::sdk::InstrumentationLibrary;
+    use opentelemetry::trace::{SpanContext, SpanId, SpanKind, StatusCode, TraceFlags, TraceState};
+    use prost::Message;
+    use reqwest::Client;
+    use std::borrow::Cow;
+    use std::io::{BufRead, BufReader, Read, Write};
+    use std::net::{TcpListener, TcpStream};
+    use std::sync::atomic::AtomicUsize;
+    use std::sync::mpsc::{self, Receiver, Sender};
+
+    fn processor(builder: DatadogPipelineBuilder) -> WASMWorkerSpanProcessor {
+        let mut builder = builder
+            .with_api_key(Some("key"))
+            .with_http_client(Arc::new(Client::new()));
+        let config = builder.take_processor_config();
+        WASMWorkerSpanProcessor::new(builder.build_exporter().unwrap(), config)
+    }
+
+    /// A Datadog intake on a local port, answering the export requests with `statuses` in turn,
+    /// the last one repeated, and sending the names of the spans of each request back.
+    fn intake(statuses: &'static [u16]) -> (String, Receiver<Vec<String>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let endpoint = format!("http://{}", listener.local_addr().unwrap());
+        let (sender, receiver) = mpsc::channel();
+        let requests = Arc::new(AtomicUsize::new(0));
+        std::thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                let sender = sender.clone();
+                let requests = Arc::clone(&requests);
+                std::thread::spawn(move || serve(stream, statuses, &requests, &sender));
+            }
+        });
+        (endpoint, receiver)
+    }
+
+    fn serve(
+        stream: TcpStream,
+        statuses: &[u16],
+        requests: &AtomicUsize,
+        sender: &Sender<Vec<String>>,
+    ) {
+        let mut reader = BufReader::new(stream);
+        while let Some(body) = read_body(&mut reader) {
+            let payload = dd_proto::TracePayload::decode(body.as_slice()).unwrap();
+            let names = payload
+                .tracer_payloads
+                .iter()
+                .flat_map(|tracer| &tracer.chunks)
+                .flat_map(|chunk| &chunk.spans)
+                .map(|span| span.name.clone())
+                .collect();
+            let _ = sender.send(names);
+            let request = requests.fetch_add(1, Ordering::Relaxed);
+            let status = statuses[request.min(statuses.len() - 1)];
+            let response = format!("HTTP/1.1 {status} Status\r\ncontent-length: 0\r\n\r\n");
+            if reader.get_mut().write_all(response.as_bytes()).is_err() {
+                return;
+            }
+        }
+    }
+
+    /// The body of the next request of the connection, `None` once it is closed.
+    fn read_body(reader: &mut BufReader<TcpStream>) -> Option<Vec<u8>> {
+        let mut request_line = String::new();
+        if reader.read_line(&mut request_line).unwrap_or(0) == 0 {
+            return None;
+        }
+        let mut content_length = 0;
+        loop {
+            let mut header = String::new();
+            reader.read_line(&mut header).unwrap();
+            if header.trim().is_empty() {
+                break;
+            }
+            if let Some((name, value)) = header.split_once(':') {
+                if name.eq_ignore_ascii_case("content-length") {
+                    content_length = value.trim().parse().unwrap();
+                }
+            }
+        }
+        let mut body = vec![0; content_length];
+        reader.read_exact(&mut body).unwrap();
+        Some(body)
+    }
+
+    /// An OTLP/HTTP collector on a local port, answering with `status` and sending the names of
+    /// the spans of each request back.
+    fn collector(status: u16) -> (String, Receiver<Vec<String>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let endpoint = format!("http://{}", listener.local_addr().unwrap());
+        let (sender, receiver) = mpsc::channel();
+        std::thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                let mut reader = BufReader::new(stream);
+                while let Some(body) = read_body(&mut reader) {
+                    let request: serde_json::Value = serde_json::from_slice(&body).unwrap();
+                    let names = request["resourceSpans"]
+                        .as_array()
+                        .into_iter()
+                        .flatten()
+                        .flat_map(|resource| resource["scopeSpans"].as_array().unwrap())
+                        .flat_map(|scope| scope["spans"].as_array().unwrap())
+                        .map(|span| span["name"].as_str().unwrap().to_string())
+                        .collect();
+                    let _ = sender.send(names);
+                    let response = format!("HTTP/1.1 {status} Status\r\ncontent-length: 0\r\n\r\n");
+                    if reader.get_mut().write_all(response.as_bytes()).is_err() {
+                        break;
+                    }
+                }
+            }
+        });
+        (endpoint, receiver)
+    }
+
+    fn span(trace_id: u128, span_id: u64, parent_id: u64, name: &'static str) -> SpanData {
+        SpanData {
+            span_context: SpanContext::new(
+                TraceId::from_u128(trace_id),
+                SpanId::from_u64(span_id),
+                TraceFlags::SAMPLED,
+                false,
+                TraceState::default(),
+            ),
+            parent_span_id: SpanId::from_u64(parent_id),
+            span_kind: SpanKind::Internal,
+            name: Cow::Borrowed(name),
+            start_time: SystemTime::UNIX_EPOCH,
+            end_time: SystemTime::UNIX_EPOCH,
+            attributes: EvictedHashMap::new(128, 0),
+            events: EvictedQueue::new(128),
+            links: EvictedQueue::new(128),
+            status_code: StatusCode::Unset,
+            status_message: Cow::Borrowed(""),
+            resource: None,
+            instrumentation_lib: InstrumentationLibrary::default(),
+        }
+    }
+
+    /// Records the names of the spans it exports.
+    #[derive(Clone, Debug, Default)]
+    struct Recorder(Arc<std::sync::Mutex<Vec<String>>>);
+
+    impl Recorder {
+        fn names(&self) -> Vec<String> {
+            self.0.lock().unwrap().clone()
+        }
+    }
+
+    #[async_trait]
+    impl SpanExporter for Recorder {
+        async fn export(
+            &mut self,
+            batch: Vec<SpanData>,
+        ) -> opentelemetry::sdk::export::trace::ExportResult {
+            self.0
+                .lock()
+                .unwrap()
+                .extend(batch.into_iter().map(|span| span.name.into_owned()));
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_force_flush_all() {
+        let (endpoint, exported) = intake(&[200]);
+        let processor = processor(new_pipeline().with_endpoint(endpoint).with_flush_size(2));
+        for span_id in 1..=5 {
+            processor.on_end(span(1, span_id, 0, "span"));
+        }
+
+        processor.force_flush_all().await.unwrap();
+        assert_eq!(processor.buffered_spans(), 0);
+        let batches: Vec<usize> = exported.try_iter().map(|names| names.len()).collect();
+        assert_eq!(batches, vec![2, 2, 1]);
+    }
+
+    #[tokio::test]
+    async fn test_stats() {
+        let (endpoint, _exported) = intake(&[200, 400]);
+        let processor = processor(new_pipeline().with_endpoint(endpoint).with_flush_size(2));
+        assert_eq!(processor.stats(), ProcessorStats::default());
+
+        for span_id in 1..=3 {
+            processor.on_end(span(1, span_id, 0, "span"));
+        }
+        assert_eq!(processor.stats().buffered_spans, 3);
+
+        SpanProcessExt::force_flush(&processor).await.unwrap();
+        let stats = processor.stats();
+        assert_eq!(stats.buffered_spans, 1);
+        assert_eq!(stats.exported_spans, 2);
+        assert!(stats.last_export.is_some());
+
+        assert!(SpanProcessExt::force_flush(&processor).await.is_err());
+        let stats = processor.stats();
+        assert_eq!(stats.buffered_spans, 0);
+        assert_eq!(stats.exported_spans, 2);
+        assert_eq!(stats.dropped_spans, 1);
+        assert_eq!(stats.export_failures, 1);
+    }
+
+    #[tokio::test]
+    async fn test_force_flush_with_timeout() {
+        // Accepts the connections without ever answering.
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let endpoint = format!("http://{}", listener.local_addr().unwrap());
+        let processor = processor(new_pipeline().with_endpoint(endpoint).with_flush_size(1));
+        processor.on_end(span(1, 1, 0, "span"));
+        processor.on_end(span(2, 1, 0, "span"));
+
+        let summary = processor
+            .force_flush_with_timeout(Duration::ZERO)
+            .await
+            .unwrap();
+        assert!(summary.timed_out);
+        assert_eq!(summary.exported_spans, 0);
+        assert_eq!(summary.remaining_spans, 2);
+
+        let summary = processor
+            .force_flush_with_timeout(Duration::from_millis(100))
+            .await
+            .unwrap();
+        assert!(summary.timed_out);
+        assert_eq!(summary.remaining_spans, 1);
+        let stats = processor.stats();
+        assert_eq!(stats.dropped_spans, 1);
+        assert_eq!(stats.export_failures, 1);
+        drop(listener);
+    }
+
+    #[tokio::test]
+    async fn test_flush_in_background() {
+        let (endpoint, exported) = intake(&[200]);
+        let processor = processor(new_pipeline().with_endpoint(endpoint).with_flush_size(1));
+        processor.on_end(span(1, 1, 0, "first"));
+        processor.on_end(span(2, 1, 0, "second"));
+
+        // A `'static` future, to be handed to `wait_until` on Workers.
+        tokio::spawn(processor.flush_in_background()).await.unwrap();
+        assert_eq!(processor.buffered_spans(), 0);
+        let mut names: Vec<String> = exported.try_iter().flatten().collect();
+        names.sort();
+        assert_eq!(names, vec!["first", "second"]);
+    }
+
+    #[test]
+    fn test_needs_flush() {
+        let processor = processor(new_pipeline().with_flush_size(2).with_auto_flush(true));
+        processor.on_end(span(1, 1, 0, "span"));
+        assert!(!processor.needs_flush());
+
+        // Outside of Workers nothing is spawned, the spans wait for the caller to flush.
+        processor.on_end(span(1, 2, 0, "span"));
+        assert!(processor.needs_flush());
+        assert_eq!(processor.buffered_spans(), 2);
+        with_buffer(|buffer| buffer.drain_ready(usize::MAX, None));
+    }
+
+    #[tokio::test]
+    async fn test_shutdown() {
+        let (endpoint, exported) = intake(&[200]);
+        let processor = processor(new_pipeline().with_endpoint(endpoint));
+        processor.on_end(span(1, 1, 0, "before"));
+
+        SpanProcessExt::shutdown(&processor).await.unwrap();
+        assert_eq!(
+            exported.try_iter().collect::<Vec<_>>(),
+            vec![vec!["before"]]
+        );
+
+        processor.on_end(span(2, 1, 0, "after"));
+        assert_eq!(processor.buffered_spans(), 0);
+        assert_eq!(processor.stats().dropped_spans, 1);
+    }
+
+    #[tokio::test]
+    async fn test_max_in_flight_exports() {
+        let (endpoint, exported) = intake(&[200, 400]);
+        let processor = processor(
+            new_pipeline()
+                .with_endpoint(endpoint)
+                .with_flush_size(1)
+                .with_max_in_flight_exports(2),
+        );
+        for trace_id in 1..=4 {
+            processor.on_end(span(trace_id, 1, 0, "span"));
+        }
+
+        // Both batches of the round are sent, one fails and the flush stops after the round.
+        assert!(processor.force_flush_all().await.is_err());
+        assert_eq!(exported.try_iter().count(), 2);
+        assert_eq!(processor.buffered_spans(), 2);
+        with_buffer(|buffer| buffer.drain_ready(usize::MAX, None));
+    }
+
+    #[test]
+    fn test_buffer_unavailable() {
+        let processor = processor(new_pipeline());
+
+        // A span ending while the buffer is borrowed is dropped instead of panicking.
+        with_buffer(|_| processor.on_end(span(1, 1, 0, "span")));
+        assert_eq!(processor.stats().dropped_spans, 1);
+
+        processor.on_end(span(1, 2, 0, "span"));
+        assert_eq!(processor.buffered_spans(), 1);
+        with_buffer(|buffer| buffer.drain_ready(usize::MAX, None));
+    }
+
+    #[test]
+    fn test_partial_flush() {
+        let processor = processor(
+            new_pipeline()
+                .with_complete_traces_only(Duration::from_secs(60))
+                .with_partial_flush_min_spans(2),
+        );
+        with_buffer(|buffer| buffer.start_local_root(SpanId::from_u64(1), time::now()));
+
+        processor.on_end(span(1, 2, 1, "child"));
+        assert_eq!(processor.ready_spans(), 0);
+        processor.on_end(span(1, 3, 1, "child"));
+        assert_eq!(processor.ready_spans(), 2);
+        processor.on_end(span(1, 1, 0, "root"));
+        assert_eq!(processor.ready_spans(), 3);
+        with_buffer(|buffer| buffer.drain_ready(usize::MAX, None));
+    }
+
+    #[test]
+    fn test_span_filter() {
+        let processor =
+            processor(new_pipeline().with_span_filter(|span| span.name != "GET /healthz"));
+        processor.on_end(span(1, 1, 0, "GET /healthz"));
+        processor.on_end(span(2, 1, 0, "GET /users"));
+
+        let kept = with_buffer(|buffer| buffer.drain_ready(usize::MAX, None)).unwrap();
+        assert_eq!(kept.len(), 1);
+        assert_eq!(kept[0].name, "GET /users");
+    }
+
+    #[tokio::test]
+    async fn test_on_export() {
+        let (endpoint, exported) = intake(&[200]);
+        let processor = processor(
+            new_pipeline()
+                .with_endpoint(endpoint)
+                .with_on_export(|span| span.name = Cow::Borrowed("renamed")),
+        );
+        processor.on_end(span(1, 1, 0, "span"));
+
+        processor.force_flush_all().await.unwrap();
+        assert_eq!(
+            exported.try_iter().collect::<Vec<_>>(),
+            vec![vec!["renamed"]]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_additional_exporter() {
+        let (endpoint, _exported) = intake(&[400]);
+        let recorder = Recorder::default();
+        let processor = processor(
+            new_pipeline()
+                .with_endpoint(endpoint)
+                .with_additional_exporter(recorder.clone()),
+        );
+        processor.on_end(span(1, 1, 0, "span"));
+
+        // The copy is delivered whatever Datadog answered.
+        assert!(processor.force_flush_all().await.is_err());
+        assert_eq!(recorder.names(), vec!["span"]);
+    }
+
+    #[test]
+    fn test_max_batch_bytes() {
+        let processor = processor(new_pipeline().with_max_batch_bytes(1500));
+        for span_id in 1..=3 {
+            let mut large = span(1, span_id, 0, "query");
+            large.attributes.insert(opentelemetry::KeyValue::new(
+                "db.statement",
+                "x".repeat(1000),
+            ));
+            processor.on_end(large);
+        }
+
+        // One span per batch, though the flush size would take them all.
+        assert_eq!(processor.drain_batch().len(), 1);
+        assert_eq!(processor.buffered_spans(), 2);
+        with_buffer(|buffer| buffer.drain_ready(usize::MAX, None));
+    }
+
+    #[tokio::test]
+    async fn test_export_error_handler() {
+        let (endpoint, _exported) = intake(&[400]);
+        let rejected = Recorder::default();
+        let handler = rejected.clone();
+        let processor = processor(
+            new_pipeline()
+                .with_endpoint(endpoint)
+                .with_export_error_handler(move |spans, _| {
+                    handler
+                        .0
+                        .lock()
+                        .unwrap()
+                        .extend(spans.iter().map(|span| span.name.to_string()));
+                }),
+        );
+        processor.on_end(span(1, 1, 0, "span"));
+
+        assert!(processor.force_flush_all().await.is_err());
+        assert_eq!(rejected.names(), vec!["span"]);
+    }
+
+    #[tokio::test]
+    async fn test_retry_buffer() {
+        let (endpoint, exported) = intake(&[503, 200]);
+        let processor = processor(new_pipeline().with_endpoint(endpoint).with_retry_buffer(10));
+        processor.on_end(span(1, 1, 0, "span"));
+
+        assert!(processor.force_flush_all().await.is_err());
+        assert_eq!(processor.buffered_spans(), 1);
+        assert_eq!(processor.stats().dropped_spans, 0);
+
+        processor.force_flush_all().await.unwrap();
+        assert_eq!(processor.buffered_spans(), 0);
+        assert_eq!(exported.try_iter().count(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_flush_summary() {
+        let (endpoint, _exported) = intake(&[200]);
+        let processor = processor(new_pipeline().with_endpoint(endpoint).with_flush_size(2));
+        for span_id in 1..=3 {
+            processor.on_end(span(1, span_id, 0, "span"));
+        }
+
+        let summary = SpanProcessExt::force_flush(&processor).await.unwrap();
+        assert_eq!(summary.exported_spans, 2);
+        assert_eq!(summary.remaining_spans, 1);
+        assert!(summary.bytes_sent > 0);
+        assert!(!summary.timed_out);
+
+        let summary = SpanProcessExt::force_flush(&processor).await.unwrap();
+        assert_eq!(summary.exported_spans, 1);
+        assert_eq!(summary.remaining_spans, 0);
+    }
+
+    #[tokio::test]
+    async fn test_flush_scheduler() {
+        #[derive(Clone, Default)]
+        struct Scheduler(Arc<std::sync::Mutex<Vec<Duration>>>);
+
+        impl FlushScheduler for Scheduler {
+            fn schedule(&self, delay: Duration) {
+                self.0.lock().unwrap().push(delay);
+            }
+        }
+
+        let (endpoint, exported) = intake(&[200]);
+        let scheduler = Scheduler::default();
+        let processor = processor(
+            new_pipeline()
+                .with_endpoint(endpoint)
+                .with_flush_scheduler(scheduler.clone(), Duration::from_secs(30)),
+        );
+        processor.on_end(span(1, 1, 0, "span"));
+        processor.on_end(span(1, 2, 0, "span"));
+        // Asked once while the flush is scheduled.
+        assert_eq!(*scheduler.0.lock().unwrap(), vec![Duration::from_secs(30)]);
+
+        processor.scheduled_flush().await.unwrap();
+        assert_eq!(exported.try_iter().flatten().count(), 2);
+        // Nothing is left to flush, so no other flush is scheduled.
+        assert_eq!(scheduler.0.lock().unwrap().len(), 1);
+
+        processor.on_end(span(2, 1, 0, "span"));
+        assert_eq!(scheduler.0.lock().unwrap().len(), 2);
+        with_buffer(|buffer| buffer.drain_ready(usize::MAX, None));
+    }
+
+    #[test]
+    fn test_install_with_processor() {
+        use opentelemetry::trace::{Span as _, Tracer as _};
+
+        let (tracer, _provider, processor) = new_pipeline()
+            .with_api_key(Some("key"))
+            .with_http_client(Arc::new(Client::new()))
+            .install_with_processor()
+            .unwrap();
+        tracer.start("span").end();
+
+        // The handle shares the buffer of the processor of the provider.
+        assert_eq!(processor.stats().buffered_spans, 1);
+        with_buffer(|buffer| buffer.drain_ready(usize::MAX, None));
+    }
+
+    #[test]
+    fn test_flush_guard() {
+        let processor = processor(new_pipeline());
+        let guard = processor.flush_guard();
+        processor.on_end(span(1, 1, 0, "span"));
+
+        // There is no executor to flush on outside of Workers, the spans stay buffered.
+        drop(guard);
+        assert_eq!(processor.buffered_spans(), 1);
+        with_buffer(|buffer| buffer.drain_ready(usize::MAX, None));
+    }
+
+    #[tokio::test]
+    async fn test_flush_request() {
+        use opentelemetry::trace::{Span as _, Tracer as _};
+
+        let (endpoint, exported) = intake(&[200]);
+        let (tracer, _provider, processor) = new_pipeline()
+            .with_api_key(Some("key"))
+            .with_http_client(Arc::new(Client::new()))
+            .with_endpoint(endpoint)
+            .install_with_processor()
+            .unwrap();
+        for request_id in ["first", "second", "first"] {
+            let cx = with_request_id(&opentelemetry::Context::current(), request_id);
+            tracer.start_with_context(request_id, &cx).end();
+        }
+
+        let summary = processor.flush_request("first").await.unwrap();
+        assert_eq!(summary.exported_spans, 2);
+        assert_eq!(summary.remaining_spans, 1);
+        assert_eq!(
+            exported.try_iter().collect::<Vec<_>>(),
+            vec![vec!["first", "first"]]
+        );
+
+        processor.flush_request("second").await.unwrap();
+        assert_eq!(processor.buffered_spans(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_otlp_fallback() {
+        let (endpoint, exported) = intake(&[400]);
+        let (collector, collected) = collector(200);
+        let fallback = OtlpHttpExporter::new(&collector)
+            .unwrap()
+            .with_http_client(Arc::new(Client::new()));
+        let processor = processor(
+            new_pipeline()
+                .with_endpoint(endpoint)
+                .with_fallback_exporter(fallback),
+        );
+        processor.on_end(span(1, 1, 0, "root"));
+        processor.on_end(span(1, 2, 1, "child"));
+
+        assert!(processor.force_flush_all().await.is_err());
+        assert_eq!(exported.try_iter().count(), 1);
+        let collected: Vec<Vec<String>> = collected.try_iter().collect();
+        assert_eq!(
+            collected,
+            vec![vec!["root".to_string(), "child".to_string()]]
+        );
+        let stats = processor.stats();
+        assert_eq!(stats.export_failures, 1);
+        assert_eq!(stats.dropped_spans, 0);
+    }
+
+    #[tokio::test]
+    async fn test_otlp_fallback_failure() {
+        let (endpoint, _exported) = intake(&[400]);
+        let (collector, collected) = collector(503);
+        let fallback = OtlpHttpExporter::new(&collector)
+            .unwrap()
+            .with_http_client(Arc::new(Client::new()));
+        let processor = processor(
+            new_pipeline()
+                .with_endpoint(endpoint)
+                .with_fallback_exporter(fallback),
+        );
+        processor.on_end(span(1, 1, 0, "root"));
+
+        assert!(processor.force_flush_all().await.is_err());
+        assert_eq!(collected.try_iter().count(), 1);
+        assert_eq!(processor.stats().dropped_spans, 1);
+    }
+
+    #[tokio::test]
+    async fn test_fallback_skipped_for_retryable_errors() {
+        let (endpoint, _exported) = intake(&[503]);
+        let recorder = Recorder::default();
+        let processor = processor(
+            new_pipeline()
+                .with_endpoint(endpoint)
+                .with_fallback_exporter(recorder.clone()),
+        );
+        processor.on_end(span(1, 1, 0, "root"));
+
+        assert!(processor.force_flush_all().await.is_err());
+        assert!(recorder.names().is_empty());
+    }
+
+    #[test]
+    fn test_trace_sample_rate() {
+        let dropping = processor(new_pipeline().with_trace_sample_rate(0.0));
+        dropping.on_end(span(1, 1, 0, "root"));
+        assert_eq!(dropping.buffered_spans(), 0);
+
+        let keeping = processor(new_pipeline().with_trace_sample_rate(1.0));
+        keeping.on_end(span(2, 1, 0, "root"));
+        assert_eq!(keeping.buffered_spans(), 1);
+        with_buffer(|buffer| buffer.drain_ready(usize::MAX, None));
+    }
+
+    #[test]
+    fn test_sync_shutdown() {
+        let mut processor = processor(new_pipeline());
+        processor.on_end(span(1, 1, 0, "root"));
+
+        assert!(SpanProcessor::shutdown(&mut processor).is_ok());
+        assert_eq!(processor.buffered_spans(), 0);
+        assert_eq!(processor.stats().dropped_spans, 1);
+    }
+
+    #[test]
+    fn test_root_sampler() {
+        let processor = processor(
+            new_pipeline()
+                .with_complete_traces_only(Duration::from_secs(60))
+                .with_trace_sampler(|span| span.name != "GET /healthz"),
+        );
+
+        // The spans of a trace are buffered until its root decides whether it is kept.
+        for (trace_id, root) in [(1, "GET /healthz"), (2, "GET /users")] {
+            with_buffer(|buffer| buffer.start_local_root(SpanId::from_u64(1), time::now()));
+            processor.on_end(span(trace_id, 2, 1, "SELECT"));
+            processor.on_end(span(trace_id, 1, 0, root));
+        }
+
+        let kept = with_buffer(|buffer| buffer.drain_ready(usize::MAX, None)).unwrap();
+        let kept: Vec<(TraceId, &str)> = kept
+            .iter()
+            .map(|span| (span.span_context.trace_id(), span.name.as_ref()))
+            .collect();
+        assert_eq!(
+            kept,
+            vec![
+                (TraceId::from_u128(2), "SELECT"),
+                (TraceId::from_u128(2), "GET /users")
+            ]
+        );
+        assert_eq!(processor.buffered_spans(), 0);
+        assert!(processor
+            .inner
+            .exporter
+            .dropped_traces
+            .contains(TraceId::from_u128(1)));
+    }
+}
@@ -0,0 +1,71 @@
+use opentelemetry::sdk::export::trace::SpanData;
+use opentelemetry::trace::TraceId;
+use std::fmt;
+use std::sync::Arc;
+
+/// Factor of the Knuth multiplicative hash used by the Datadog libraries to sample by trace id,
+/// so a trace is kept or dropped consistently across services with the same rate.
+const KNUTH_FACTOR: u64 = 1_111_111_111_111_111_111;
+
+/// Head sampling applied by the processor before buffering, see
+/// [`DatadogPipelineBuilder::with_trace_sample_rate`](crate::DatadogPipelineBuilder::with_trace_sample_rate)
+/// and [`DatadogPipelineBuilder::with_trace_sampler`](crate::DatadogPipelineBuilder::with_trace_sampler).
+#[derive(Clone, Default)]
+pub(crate) struct TraceSampling {
+    pub(crate) rate: Option<f64>,
+    /// Decides on the local root span whether its trace is kept.
+    pub(crate) root_sampler: Option<Arc<dyn Fn(&SpanData) -> bool + Send + Sync>>,
+}
+
+impl fmt::Debug for TraceSampling {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TraceSampling")
+            .field("rate", &self.rate)
+            .field("root_sampler", &self.root_sampler.is_some())
+            .finish()
+    }
+}
+
+/// Whether the trace is kept with the sample `rate`, based on the lower 64 bits of its id like
+/// the Datadog libraries.
+#[allow(
+    clippy::cast_precision_loss,
+    clippy::cast_possible_truncation,
+    clippy::cast_sign_loss
+)]
+pub(crate) fn keep_trace(trace_id: TraceId, rate: f64) -> bool {
+    if rate >= 1.0 {
+        return true;
+    }
+    if rate <= 0.0 {
+        return false;
+    }
+
+    let low = u128::from_be_bytes(trace_id.to_bytes()) as u64;
+    low.wrapping_mul(KNUTH_FACTOR) <= (rate * u64::MAX as f64) as u64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_keep_trace() {
+        let trace_ids = (1..=1000u128).map(|n| TraceId::from_u128(n * 7_919 + (n << 64)));
+
+        let kept = trace_ids
+            .clone()
+            .filter(|trace_id| keep_trace(*trace_id, 0.25))
+            .count();
+        assert!((150..350).contains(&kept), "kept {kept} traces out of 1000");
+
+        assert!(trace_ids.clone().all(|trace_id| keep_trace(trace_id, 1.0)));
+        assert!(!trace_ids.clone().any(|trace_id| keep_trace(trace_id, 0.0)));
+
+        // The decision only depends on the lower 64 bits, like the Datadog libraries.
+        assert_eq!(
+            keep_trace(TraceId::from_u128(42), 0.5),
+            keep_trace(TraceId::from_u128(42 + (7 << 64)), 0.5)
+        );
+    }
+}
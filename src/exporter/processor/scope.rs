@@ -0,0 +1,30 @@
+use opentelemetry::trace::SpanId;
+use opentelemetry::Context;
+use std::sync::Arc;
+
+use super::with_buffer;
+
+/// Request the spans started in a context belong to, see [`with_request_id`].
+#[derive(Clone, Debug)]
+pub(super) struct RequestScope(pub(super) Arc<str>);
+
+/// Forgets the request of the spans of an export when dropped, once the export completed or
+/// was abandoned, see [`SpanBuffer::release_scopes`](super::buffer::SpanBuffer::release_scopes).
+pub(super) struct ScopeRelease(pub(super) Vec<SpanId>);
+
+impl Drop for ScopeRelease {
+    fn drop(&mut self) {
+        with_buffer(|buffer| buffer.release_scopes(&self.0));
+    }
+}
+
+/// Mark the spans started in the returned context, and in the contexts derived from it, as
+/// belonging to the request `request_id`.
+///
+/// The spans of a request can then be exported on their own with
+/// [`WASMWorkerSpanProcessor::flush_request`](super::WASMWorkerSpanProcessor::flush_request),
+/// without flushing the spans of the other requests handled concurrently by the isolate.
+#[must_use]
+pub fn with_request_id(cx: &Context, request_id: impl Into<String>) -> Context {
+    cx.with_value(RequestScope(Arc::from(request_id.into())))
+}
@@ -0,0 +1,242 @@
+use async_trait::async_trait;
+use prost::Message;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::sync::Arc;
+
+use super::transport::ExportRequest;
+use super::{endpoint_url, AuthScheme, Error, CONTENT_TYPE_HEADER, DEFAULT_DD_CONTENT_TYPE};
+use crate::pubsub_proto::{PublishRequest, PubsubMessage};
+
+/// The Pub/Sub API, the `PublishRequest`s are posted to `v1/{topic}:publish`.
+pub(crate) const DEFAULT_PUBSUB_ENDPOINT: &str = "https://pubsub.googleapis.com/";
+
+/// Message attribute holding the url the payload was meant for.
+const URL_ATTRIBUTE: &str = "url";
+
+/// Where the OAuth 2 access tokens authorizing the publication come from, e.g. the metadata
+/// server of the instance or a service account key exchanged for tokens, see
+/// [`DatadogPipelineBuilder::with_pubsub_token_provider`](super::DatadogPipelineBuilder::with_pubsub_token_provider).
+///
+/// The token is asked for on every export, providers should cache it until it expires. As for
+/// [`ApiKeyProvider`](super::ApiKeyProvider), the future of [`get_token`](Self::get_token) is
+/// `Send` outside of Workers.
+#[cfg_attr(target_arch = "wasm32", async_trait(?Send))]
+#[cfg_attr(not(target_arch = "wasm32"), async_trait)]
+pub trait AccessTokenProvider: Send + Sync {
+    /// A current access token with the `https://www.googleapis.com/auth/pubsub` scope.
+    ///
+    /// # Errors
+    ///
+    /// If no token can be obtained, the export then fails with this error.
+    async fn get_token(&self) -> Result<String, Error>;
+}
+
+/// The provider given to [`DatadogPipelineBuilder::with_pubsub_token_provider`](super::DatadogPipelineBuilder::with_pubsub_token_provider).
+#[derive(Clone)]
+pub(crate) struct AccessTokenProviderHandle(pub(crate) Arc<dyn AccessTokenProvider>);
+
+impl fmt::Debug for AccessTokenProviderHandle {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("AccessTokenProvider")
+    }
+}
+
+/// Where the payloads are published instead of being sent to Datadog, see
+/// [`DatadogPipelineBuilder::with_pubsub_topic`](super::DatadogPipelineBuilder::with_pubsub_topic).
+#[derive(Clone, Debug)]
+pub(crate) struct PubSubTarget {
+    /// `projects/{project}/topics/{topic}`
+    pub(crate) topic: String,
+    /// Where the `PublishRequest`s are posted, the `publish` method of the topic.
+    pub(crate) url: String,
+    pub(crate) token_provider: Option<AccessTokenProviderHandle>,
+}
+
+impl PubSubTarget {
+    /// Publish to `topic` with the Pub/Sub API at `endpoint`, e.g. an emulator.
+    pub(crate) fn new(
+        endpoint: &str,
+        topic: String,
+        token_provider: Option<AccessTokenProviderHandle>,
+    ) -> Result<Self, Error> {
+        match topic.split('/').collect::<Vec<_>>()[..] {
+            ["projects", project, "topics", name] if !project.is_empty() && !name.is_empty() => {}
+            _ => {
+                return Err(Error::Other(format!(
+                    "invalid Pub/Sub topic {topic}, expected projects/{{project}}/topics/{{topic}}"
+                )))
+            }
+        }
+        let url = format!(
+            "{}:publish",
+            endpoint_url(endpoint, &format!("v1/{topic}"))?
+        );
+        Ok(PubSubTarget {
+            topic,
+            url,
+            token_provider,
+        })
+    }
+
+    /// Authorize the `PublishRequest`s with a token of the provider, if there is one.
+    pub(crate) async fn authorize(
+        &self,
+        requests: Vec<ExportRequest>,
+    ) -> Result<Vec<ExportRequest>, Error> {
+        let Some(AccessTokenProviderHandle(provider)) = &self.token_provider else {
+            return Ok(requests);
+        };
+        let token = provider.get_token().await?;
+        Ok(requests
+            .into_iter()
+            .map(|request| AuthScheme::Bearer.authenticate(request, &token))
+            .collect())
+    }
+
+    /// Wrap the request meant for Datadog in a `PublishRequest`.
+    ///
+    /// The payload is the message data and its headers become the message attributes, along with
+    /// its url, so a forwarder can replay it. The API key, sent as `auth` describes, is left out,
+    /// the forwarder holds its own.
+    pub(crate) fn wrap(&self, request: ExportRequest, auth: &AuthScheme) -> ExportRequest {
+        let attributes: BTreeMap<String, String> = request
+            .headers
+            .into_iter()
+            .filter(|(name, _)| !auth.is_auth_header(name))
+            .map(|(name, value)| (name.to_ascii_lowercase(), value))
+            .chain([(URL_ATTRIBUTE.to_string(), request.url)])
+            .collect();
+
+        let publish = PublishRequest {
+            topic: self.topic.clone(),
+            messages: vec![PubsubMessage {
+                data: request.body.to_vec(),
+                attributes,
+                ..Default::default()
+            }],
+        };
+
+        ExportRequest::post(self.url.clone(), publish.encode_to_vec())
+            .header(CONTENT_TYPE_HEADER, DEFAULT_DD_CONTENT_TYPE)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::exporter::DEFAULT_DD_API_KEY_HEADER;
+
+    fn target(token_provider: Option<AccessTokenProviderHandle>) -> PubSubTarget {
+        PubSubTarget::new(
+            DEFAULT_PUBSUB_ENDPOINT,
+            "projects/telemetry/topics/traces".to_string(),
+            token_provider,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn test_publish_url() {
+        assert_eq!(
+            target(None).url,
+            "https://pubsub.googleapis.com/v1/projects/telemetry/topics/traces:publish"
+        );
+        let emulator = PubSubTarget::new(
+            "http://localhost:8085",
+            "projects/telemetry/topics/traces".to_string(),
+            None,
+        )
+        .unwrap();
+        assert_eq!(
+            emulator.url,
+            "http://localhost:8085/v1/projects/telemetry/topics/traces:publish"
+        );
+
+        for topic in [
+            "traces",
+            "projects/telemetry/traces",
+            "projects//topics/traces",
+        ] {
+            assert!(
+                matches!(
+                    PubSubTarget::new(DEFAULT_PUBSUB_ENDPOINT, topic.to_string(), None),
+                    Err(Error::Other(_))
+                ),
+                "{topic}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_authorize() {
+        use futures_util::FutureExt;
+
+        struct StaticToken;
+
+        #[cfg_attr(target_arch = "wasm32", async_trait(?Send))]
+        #[cfg_attr(not(target_arch = "wasm32"), async_trait)]
+        impl AccessTokenProvider for StaticToken {
+            async fn get_token(&self) -> Result<String, Error> {
+                Ok("ya29.token".to_string())
+            }
+        }
+
+        let request = || ExportRequest::post(target(None).url, vec![1, 2, 3]);
+        let authorized = target(Some(AccessTokenProviderHandle(Arc::new(StaticToken))))
+            .authorize(vec![request(), request()])
+            .now_or_never()
+            .unwrap()
+            .unwrap();
+        assert_eq!(authorized.len(), 2);
+        for request in &authorized {
+            assert_eq!(
+                request.header_value("Authorization"),
+                Some("Bearer ya29.token")
+            );
+        }
+
+        let unauthorized = target(None)
+            .authorize(vec![request()])
+            .now_or_never()
+            .unwrap()
+            .unwrap();
+        assert_eq!(unauthorized[0].header_value("Authorization"), None);
+    }
+
+    #[test]
+    fn test_wrap() {
+        let target = target(None);
+        let request = ExportRequest::post(
+            "https://trace.agent.datadoghq.eu/api/v0.2/traces".to_string(),
+            vec![1, 2, 3],
+        )
+        .header(CONTENT_TYPE_HEADER, DEFAULT_DD_CONTENT_TYPE)
+        .header(DEFAULT_DD_API_KEY_HEADER, "secret");
+
+        let wrapped = target.wrap(request, &AuthScheme::default());
+
+        assert_eq!(
+            wrapped.url,
+            "https://pubsub.googleapis.com/v1/projects/telemetry/topics/traces:publish"
+        );
+        let publish = PublishRequest::decode(wrapped.body).unwrap();
+        assert_eq!(publish.topic, "projects/telemetry/topics/traces");
+        assert_eq!(publish.messages.len(), 1);
+        let message = &publish.messages[0];
+        assert_eq!(message.data, vec![1, 2, 3]);
+        assert_eq!(
+            message.attributes,
+            BTreeMap::from([
+                (
+                    "content-type".to_string(),
+                    DEFAULT_DD_CONTENT_TYPE.to_string()
+                ),
+                (
+                    "url".to_string(),
+                    "https://trace.agent.datadoghq.eu/api/v0.2/traces".to_string()
+                ),
+            ])
+        );
+    }
+}
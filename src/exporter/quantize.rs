@@ -0,0 +1,64 @@
+//! Quantization of the URLs the resources are derived from, so the resources of a parameterized
+//! path don't explode into one per parameter value.
+
+/// The path of `url`, without its scheme, host, query string and fragment, whose numeric and UUID
+/// segments are replaced by `?`, e.g. `/users/?/posts` for
+/// `https://example.com/users/42/posts?page=2`.
+pub(crate) fn quantize_url(url: &str) -> String {
+    let path = match url.split_once("://") {
+        Some((_, rest)) => rest.find('/').map_or("", |start| &rest[start..]),
+        None => url,
+    };
+    let path = path.split(['?', '#']).next().unwrap_or_default();
+    if path.is_empty() {
+        return "/".to_string();
+    }
+
+    path.split('/')
+        .map(|segment| {
+            if is_numeric(segment) || is_uuid(segment) {
+                "?"
+            } else {
+                segment
+            }
+        })
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+fn is_numeric(segment: &str) -> bool {
+    !segment.is_empty() && segment.bytes().all(|byte| byte.is_ascii_digit())
+}
+
+/// Whether `segment` is a UUID in its hyphenated form.
+fn is_uuid(segment: &str) -> bool {
+    segment.len() == 36
+        && segment
+            .bytes()
+            .enumerate()
+            .all(|(index, byte)| match index {
+                8 | 13 | 18 | 23 => byte == b'-',
+                _ => byte.is_ascii_hexdigit(),
+            })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_quantize_url() {
+        assert_eq!(
+            quantize_url("https://example.com/users/42/posts?page=2"),
+            "/users/?/posts"
+        );
+        assert_eq!(
+            quantize_url("https://example.com/orders/0b6f4f3a-8c1e-4c5e-9f2a-1d2e3f4a5b6c#items"),
+            "/orders/?"
+        );
+        assert_eq!(quantize_url("/v1/graphql"), "/v1/graphql");
+        assert_eq!(quantize_url("https://example.com"), "/");
+        assert_eq!(quantize_url("https://example.com/?query=1"), "/");
+        assert_eq!(quantize_url("/items/sku42/"), "/items/sku42/");
+    }
+}
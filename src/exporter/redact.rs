@@ -0,0 +1,137 @@
+//! Redaction of the attributes which may hold secrets, e.g. the authorization headers recorded by
+//! an HTTP instrumentation, and scrubbing of the personal data in the tags, so they never leave
+//! the worker.
+
+use regex::Regex;
+use std::borrow::Cow;
+use std::collections::HashSet;
+
+use super::Error;
+
+/// Value of the redacted attributes.
+pub(crate) const REDACTED: &str = "[REDACTED]";
+
+/// Attributes redacted, set by
+/// [`DatadogPipelineBuilder::with_redacted_attributes`](super::DatadogPipelineBuilder::with_redacted_attributes)
+/// and [`DatadogPipelineBuilder::with_redacted_attribute_patterns`](super::DatadogPipelineBuilder::with_redacted_attribute_patterns).
+#[derive(Clone, Debug, Default)]
+pub(crate) struct Redaction {
+    keys: HashSet<String>,
+    patterns: Vec<Regex>,
+}
+
+impl Redaction {
+    /// Redact the attributes named `keys` or whose key matches one of `patterns`.
+    pub(crate) fn new(keys: Vec<String>, patterns: &[String]) -> Result<Self, Error> {
+        let patterns = patterns
+            .iter()
+            .map(|pattern| {
+                Regex::new(pattern)
+                    .map_err(|e| Error::Other(format!("invalid redaction pattern {pattern}: {e}")))
+            })
+            .collect::<Result<_, _>>()?;
+        Ok(Redaction {
+            keys: keys.into_iter().collect(),
+            patterns,
+        })
+    }
+
+    /// Whether the value of the attribute `key` is redacted.
+    pub(crate) fn redacts(&self, key: &str) -> bool {
+        self.keys.contains(key) || self.patterns.iter().any(|pattern| pattern.is_match(key))
+    }
+}
+
+/// Personal data scrubbed by default: emails, credit card numbers and bearer tokens.
+const PII_PATTERNS: &[&str] = &[
+    r"[A-Za-z0-9._%+-]+@[A-Za-z0-9.-]+\.[A-Za-z]{2,}",
+    r"\b(?:\d[ -]?){12,18}\d\b",
+    r"(?i)\bbearer\s+[A-Za-z0-9\-._~+/]+=*",
+];
+
+/// Scrubs the personal data out of the meta values of the spans, set by
+/// [`DatadogPipelineBuilder::with_pii_scrubbing`](super::DatadogPipelineBuilder::with_pii_scrubbing).
+#[derive(Clone, Debug)]
+pub(crate) struct Scrubber {
+    patterns: Vec<Regex>,
+}
+
+impl Scrubber {
+    /// Scrub the built-in patterns and the regular expressions `patterns`.
+    pub(crate) fn new(patterns: &[String]) -> Result<Self, Error> {
+        let patterns = PII_PATTERNS
+            .iter()
+            .copied()
+            .chain(patterns.iter().map(String::as_str))
+            .map(|pattern| {
+                Regex::new(pattern)
+                    .map_err(|e| Error::Other(format!("invalid scrubbing pattern {pattern}: {e}")))
+            })
+            .collect::<Result<_, _>>()?;
+        Ok(Scrubber { patterns })
+    }
+
+    /// Replace the matches of the patterns in `value` with `[REDACTED]`.
+    pub(crate) fn scrub(&self, value: &mut String) {
+        for pattern in &self.patterns {
+            if let Cow::Owned(scrubbed) = pattern.replace_all(value, REDACTED) {
+                *value = scrubbed;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_redaction() {
+        let redaction = Redaction::new(
+            vec!["http.request.header.authorization".to_string()],
+            &["(?i)token|secret".to_string()],
+        )
+        .unwrap();
+
+        assert!(redaction.redacts("http.request.header.authorization"));
+        assert!(redaction.redacts("github.access_token"));
+        assert!(redaction.redacts("stripe.SECRET_KEY"));
+        assert!(!redaction.redacts("http.request.header.accept"));
+        assert!(!Redaction::default().redacts("github.access_token"));
+
+        assert!(matches!(
+            Redaction::new(Vec::new(), &["(".to_string()]),
+            Err(Error::Other(_))
+        ));
+    }
+
+    #[test]
+    fn test_scrubber() {
+        let scrubber = Scrubber::new(&[r"\bsk_live_\w+".to_string()]).unwrap();
+        let scrub = |value: &str| {
+            let mut value = value.to_string();
+            scrubber.scrub(&mut value);
+            value
+        };
+
+        assert_eq!(
+            scrub("sent to jane.doe+work@example.com"),
+            "sent to [REDACTED]"
+        );
+        assert_eq!(
+            scrub("card 4242 4242 4242 4242 declined"),
+            "card [REDACTED] declined"
+        );
+        assert_eq!(
+            scrub("Authorization: Bearer eyJhbGciOi.J9.x_y"),
+            "Authorization: [REDACTED]"
+        );
+        assert_eq!(scrub("key sk_live_abc123"), "key [REDACTED]");
+        assert_eq!(scrub("GET /users/42"), "GET /users/42");
+
+        assert!(matches!(
+            Scrubber::new(&["[".to_string()]),
+            Err(Error::Other(_))
+        ));
+    }
+}
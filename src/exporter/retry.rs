@@ -0,0 +1,87 @@
+use std::time::{Duration, SystemTime};
+
+use super::time;
+
+/// How exports failing with a retryable error, e.g. a 502 from the intake or a connection
+/// error, are retried, see
+/// [`DatadogPipelineBuilder::with_retry_policy`](super::DatadogPipelineBuilder::with_retry_policy).
+///
+/// The delay before each retry doubles from `initial_backoff` up to `max_backoff`, with a random
+/// jitter of up to half of it so isolates failing together don't retry together.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RetryPolicy {
+    /// Retries after the first attempt, `0` disables retries.
+    pub max_retries: u32,
+    /// Delay before the first retry.
+    pub initial_backoff: Duration,
+    /// Upper bound of the delay between two retries.
+    pub max_backoff: Duration,
+}
+
+impl Default for RetryPolicy {
+    /// 3 retries, waiting from 100ms up to 2s.
+    fn default() -> Self {
+        RetryPolicy {
+            max_retries: 3,
+            initial_backoff: Duration::from_millis(100),
+            max_backoff: Duration::from_secs(2),
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Never retry, the default of the pipeline.
+    #[must_use]
+    pub fn none() -> Self {
+        RetryPolicy {
+            max_retries: 0,
+            ..RetryPolicy::default()
+        }
+    }
+
+    /// Delay before the retry number `retry`, starting at 0.
+    pub(crate) fn backoff(&self, retry: u32) -> Duration {
+        let backoff = self
+            .initial_backoff
+            .saturating_mul(2u32.saturating_pow(retry))
+            .min(self.max_backoff);
+
+        backoff / 2 + jitter(backoff / 2)
+    }
+}
+
+/// A pseudo random duration up to `max`, good enough to spread retries without a RNG.
+fn jitter(max: Duration) -> Duration {
+    let nanos = time::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .map(|elapsed| elapsed.subsec_nanos())
+        .unwrap_or_default();
+    let max_nanos = max.as_nanos().max(1);
+
+    #[allow(clippy::cast_possible_truncation)]
+    Duration::from_nanos((u128::from(nanos) % max_nanos) as u64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_backoff() {
+        let policy = RetryPolicy {
+            max_retries: 5,
+            initial_backoff: Duration::from_millis(100),
+            max_backoff: Duration::from_millis(300),
+        };
+
+        let expected = [100, 200, 300, 300];
+        for (retry, millis) in (0..).zip(expected) {
+            let backoff = policy.backoff(retry);
+            let max = Duration::from_millis(millis);
+            assert!(
+                backoff >= max / 2 && backoff <= max,
+                "retry {retry} waits {backoff:?}"
+            );
+        }
+    }
+}
@@ -0,0 +1,115 @@
+use async_trait::async_trait;
+use futures_util::future::join_all;
+use opentelemetry::sdk::export::trace::{ExportResult, SpanData, SpanExporter};
+use opentelemetry::sdk::export::ExportError;
+use opentelemetry::trace::TraceError;
+use std::fmt;
+
+/// Sends every batch to all of its exporters, e.g. to Datadog and to an OTLP collector while
+/// migrating from one to the other.
+///
+/// The exporters get the batch concurrently, one failing doesn't keep the others from getting
+/// it. The export then fails with a [`TeeError`] telling which exporters failed.
+#[derive(Debug, Default)]
+pub struct TeeExporter(pub Vec<Box<dyn SpanExporter>>);
+
+#[async_trait]
+impl SpanExporter for TeeExporter {
+    async fn export(&mut self, batch: Vec<SpanData>) -> ExportResult {
+        let results = join_all(
+            self.0
+                .iter_mut()
+                .map(|exporter| exporter.export(batch.clone())),
+        )
+        .await;
+
+        let failures: Vec<(usize, TraceError)> = results
+            .into_iter()
+            .enumerate()
+            .filter_map(|(index, result)| result.err().map(|err| (index, err)))
+            .collect();
+        if failures.is_empty() {
+            Ok(())
+        } else {
+            Err(TeeError { failures }.into())
+        }
+    }
+
+    fn shutdown(&mut self) {
+        for exporter in &mut self.0 {
+            exporter.shutdown();
+        }
+    }
+}
+
+/// The exporters of a [`TeeExporter`] which failed to export a batch.
+#[derive(Debug)]
+pub struct TeeError {
+    /// The index of each failed exporter in the [`TeeExporter`], with its error.
+    pub failures: Vec<(usize, TraceError)>,
+}
+
+impl fmt::Display for TeeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} tee exporter(s) failed", self.failures.len())?;
+        for (index, err) in &self.failures {
+            write!(f, "; exporter {index}: {err}")?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for TeeError {}
+
+impl ExportError for TeeError {
+    fn exporter_name(&self) -> &'static str {
+        "tee"
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures_util::FutureExt;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    #[derive(Debug)]
+    struct CountingExporter {
+        exports: Arc<AtomicUsize>,
+        fail: bool,
+    }
+
+    #[async_trait]
+    impl SpanExporter for CountingExporter {
+        async fn export(&mut self, _batch: Vec<SpanData>) -> ExportResult {
+            self.exports.fetch_add(1, Ordering::Relaxed);
+            if self.fail {
+                Err(TraceError::Other("intake unavailable".into()))
+            } else {
+                Ok(())
+            }
+        }
+    }
+
+    #[test]
+    fn test_tee_exports_to_every_exporter() {
+        let exports = Arc::new(AtomicUsize::new(0));
+        let exporter = |fail| -> Box<dyn SpanExporter> {
+            Box::new(CountingExporter {
+                exports: Arc::clone(&exports),
+                fail,
+            })
+        };
+        let mut tee = TeeExporter(vec![exporter(false), exporter(true), exporter(false)]);
+
+        let result = tee.export(Vec::new()).now_or_never().unwrap();
+
+        assert_eq!(exports.load(Ordering::Relaxed), 3);
+        let err = result.unwrap_err();
+        assert!(matches!(&err, TraceError::ExportFailed(err) if err.exporter_name() == "tee"));
+        assert!(err
+            .to_string()
+            .contains("1 tee exporter(s) failed; exporter 1: intake unavailable"));
+    }
+}
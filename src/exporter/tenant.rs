@@ -0,0 +1,27 @@
+use opentelemetry::sdk::export::trace::SpanData;
+use std::fmt;
+use std::sync::Arc;
+
+/// Where the spans of a tenant are sent instead of the endpoint of the pipeline, with another API
+/// key, see
+/// [`DatadogPipelineBuilder::with_tenant_router`](super::DatadogPipelineBuilder::with_tenant_router).
+#[derive(Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub struct TenantTarget {
+    /// API key of the Datadog organization of the tenant, the key of the pipeline when `None`.
+    pub api_key: Option<String>,
+    /// Endpoint of the tenant, e.g. the intake of another Datadog site, the endpoint of the
+    /// pipeline when `None`.
+    pub endpoint: Option<String>,
+}
+
+/// Callback given to [`DatadogPipelineBuilder::with_tenant_router`](super::DatadogPipelineBuilder::with_tenant_router).
+#[derive(Clone)]
+pub(crate) struct TenantRouter(
+    pub(crate) Arc<dyn Fn(&SpanData) -> Option<TenantTarget> + Send + Sync>,
+);
+
+impl fmt::Debug for TenantRouter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("TenantRouter")
+    }
+}
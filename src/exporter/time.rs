@@ -0,0 +1,102 @@
+use std::future::{poll_fn, Future};
+use std::pin::Pin;
+use std::task::Poll;
+use std::time::{Duration, SystemTime};
+
+/// `SystemTime::now()` panics on `wasm32-unknown-unknown`, read the clock from JavaScript there.
+#[cfg(target_arch = "wasm32")]
+pub(crate) fn now() -> SystemTime {
+    #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+    let millis = js_sys::Date::now() as u64;
+    SystemTime::UNIX_EPOCH + Duration::from_millis(millis)
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+pub(crate) fn now() -> SystemTime {
+    SystemTime::now()
+}
+
+/// Milliseconds since the unix epoch, `0` for times before it.
+pub(crate) fn to_unix_millis(time: SystemTime) -> u64 {
+    time.duration_since(SystemTime::UNIX_EPOCH)
+        .map(|elapsed| u64::try_from(elapsed.as_millis()).unwrap_or(u64::MAX))
+        .unwrap_or_default()
+}
+
+pub(crate) fn from_unix_millis(millis: u64) -> SystemTime {
+    SystemTime::UNIX_EPOCH + Duration::from_millis(millis)
+}
+
+/// The deadline of a [`timeout`] was reached before the future completed.
+#[derive(Debug)]
+pub(crate) struct Elapsed;
+
+/// A future completing after `duration`, backed by `setTimeout` on Workers.
+#[cfg(target_arch = "wasm32")]
+pub(crate) fn sleep(duration: Duration) -> Pin<Box<dyn Future<Output = ()> + Send>> {
+    use wasm_bindgen::{JsCast, JsValue};
+
+    #[allow(clippy::cast_precision_loss)]
+    let millis = duration.as_millis() as f64;
+    let promise = js_sys::Promise::new(&mut |resolve, _reject| {
+        let global = js_sys::global();
+        if let Ok(set_timeout) = js_sys::Reflect::get(&global, &JsValue::from_str("setTimeout")) {
+            let set_timeout: js_sys::Function = set_timeout.unchecked_into();
+            let _ = set_timeout.call2(&global, &resolve, &JsValue::from_f64(millis));
+        }
+    });
+    let future = wasm_bindgen_futures::JsFuture::from(promise);
+
+    Box::pin(send_wrapper::SendWrapper::new(async move {
+        let _ = future.await;
+    }))
+}
+
+/// A future completing after `duration`, backed by the timer thread of `futures-timer` outside
+/// of Workers, shared by all the sleeps.
+#[cfg(not(target_arch = "wasm32"))]
+pub(crate) fn sleep(duration: Duration) -> Pin<Box<dyn Future<Output = ()> + Send>> {
+    Box::pin(futures_timer::Delay::new(duration))
+}
+
+/// Race `future` against a `duration` deadline.
+pub(crate) async fn timeout<F: Future>(
+    duration: Duration,
+    future: F,
+) -> Result<F::Output, Elapsed> {
+    let mut future = Box::pin(future);
+    let mut delay = sleep(duration);
+
+    poll_fn(|cx| {
+        if let Poll::Ready(output) = future.as_mut().poll(cx) {
+            return Poll::Ready(Ok(output));
+        }
+        delay.as_mut().poll(cx).map(|()| Err(Elapsed))
+    })
+    .await
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use super::*;
+    use futures_util::future::{join_all, pending};
+
+    #[tokio::test]
+    async fn test_sleep() {
+        let start = std::time::Instant::now();
+        join_all((0..1000).map(|_| sleep(Duration::from_millis(10)))).await;
+        assert!(start.elapsed() >= Duration::from_millis(10));
+    }
+
+    #[tokio::test]
+    async fn test_timeout() {
+        assert!(matches!(
+            timeout(Duration::from_millis(10), pending::<()>()).await,
+            Err(Elapsed)
+        ));
+        assert!(matches!(
+            timeout(Duration::from_secs(10), sleep(Duration::from_millis(1))).await,
+            Ok(())
+        ));
+    }
+}
@@ -0,0 +1,80 @@
+use http::header::{HeaderMap, HeaderName, HeaderValue};
+use opentelemetry::{
+    global,
+    propagation::{Extractor, Injector},
+    Context,
+};
+
+struct HeaderInjector<'a>(&'a mut HeaderMap);
+
+impl<'a> Injector for HeaderInjector<'a> {
+    fn set(&mut self, key: &str, value: String) {
+        if let Ok(name) = HeaderName::from_bytes(key.as_bytes()) {
+            if let Ok(value) = HeaderValue::from_str(&value) {
+                self.0.insert(name, value);
+            }
+        }
+    }
+}
+
+struct HeaderExtractor<'a>(&'a HeaderMap);
+
+impl<'a> Extractor for HeaderExtractor<'a> {
+    fn get(&self, key: &str) -> Option<&str> {
+        self.0.get(key).and_then(|value| value.to_str().ok())
+    }
+
+    fn keys(&self) -> Vec<&str> {
+        self.0.keys().map(HeaderName::as_str).collect()
+    }
+}
+
+/// Inject the span context of `cx` into `headers` using the global text map propagator.
+///
+/// Install a [`DatadogPropagator`](crate::DatadogPropagator) with
+/// `opentelemetry::global::set_text_map_propagator` to get Datadog headers.
+pub fn inject_into_headers(cx: &Context, headers: &mut HeaderMap) {
+    global::get_text_map_propagator(|propagator| {
+        propagator.inject_context(cx, &mut HeaderInjector(headers));
+    });
+}
+
+/// Extract a [`Context`] from `headers` using the global text map propagator.
+#[must_use]
+pub fn extract_from_headers(headers: &HeaderMap) -> Context {
+    global::get_text_map_propagator(|propagator| propagator.extract(&HeaderExtractor(headers)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::DatadogPropagator;
+    use opentelemetry::testing::trace::TestSpan;
+    use opentelemetry::trace::{
+        SpanContext, SpanId, TraceContextExt, TraceFlags, TraceId, TraceState,
+    };
+
+    #[test]
+    fn test_header_map_round_trip() {
+        global::set_text_map_propagator(DatadogPropagator::default());
+
+        let span_context = SpanContext::new(
+            TraceId::from_u128(1234),
+            SpanId::from_u64(12),
+            TraceFlags::SAMPLED,
+            true,
+            TraceState::default(),
+        );
+        let mut headers = HeaderMap::new();
+        inject_into_headers(
+            &Context::current_with_span(TestSpan(span_context.clone())),
+            &mut headers,
+        );
+
+        assert_eq!(headers.get("x-datadog-trace-id").unwrap(), "1234");
+        assert_eq!(headers.get("x-datadog-parent-id").unwrap(), "12");
+
+        let context = extract_from_headers(&headers);
+        assert_eq!(context.span().span_context(), &span_context);
+    }
+}
@@ -0,0 +1,1172 @@
+use std::str::FromStr;
+
+use opentelemetry::{
+    propagation::{text_map_propagator::FieldIter, Extractor, Injector, TextMapPropagator},
+    sdk::propagation::TraceContextPropagator,
+    trace::{SpanContext, SpanId, TraceContextExt, TraceFlags, TraceId, TraceState},
+    Context,
+};
+
+use crate::Error;
+
+mod header_map;
+#[cfg(feature = "worker")]
+mod queue;
+mod sampling;
+#[cfg(feature = "worker")]
+mod worker;
+
+pub use self::header_map::{extract_from_headers, inject_into_headers};
+pub(crate) use self::sampling::{
+    origin_from_trace_state, propagated_tags_from_trace_state, sampling_priority_from_trace_state,
+};
+use self::sampling::{
+    trace_state_with_origin, trace_state_with_propagated_tags, trace_state_with_sampling_priority,
+};
+pub use self::sampling::{with_sampling_priority, SamplingPriority};
+
+#[cfg(feature = "worker")]
+pub use self::queue::TracedMessage;
+#[cfg(feature = "worker")]
+pub use self::worker::{WorkerHeadersExtractor, WorkerHeadersInjector};
+
+/// Header carrying the lower 64 bits of the trace id, in decimal.
+pub const DATADOG_TRACE_ID_HEADER: &str = "x-datadog-trace-id";
+/// Header carrying the id of the parent span, in decimal.
+pub const DATADOG_PARENT_ID_HEADER: &str = "x-datadog-parent-id";
+/// Header carrying the [`SamplingPriority`] of the trace.
+pub const DATADOG_SAMPLING_PRIORITY_HEADER: &str = "x-datadog-sampling-priority";
+/// Header carrying the product the trace originates from (`rum`, `synthetics`, ...).
+pub const DATADOG_ORIGIN_HEADER: &str = "x-datadog-origin";
+/// Header carrying the `_dd.p.*` propagated trace tags.
+pub const DATADOG_TAGS_HEADER: &str = "x-datadog-tags";
+
+const TRACEPARENT_HEADER: &str = "traceparent";
+const TRACESTATE_HEADER: &str = "tracestate";
+
+const B3_TRACE_ID_HEADER: &str = "x-b3-traceid";
+const B3_SPAN_ID_HEADER: &str = "x-b3-spanid";
+const B3_SAMPLED_HEADER: &str = "x-b3-sampled";
+const B3_FLAGS_HEADER: &str = "x-b3-flags";
+
+pub(crate) const TRACE_FLAG_DEFERRED: TraceFlags = TraceFlags::new(0x02);
+
+#[derive(Debug)]
+enum ExtractError {
+    TraceId,
+    SpanId,
+    SamplingPriority,
+}
+
+/// Header formats the [`DatadogPropagator`] can extract from and inject into.
+///
+/// The values mirror the ones accepted by `DD_TRACE_PROPAGATION_STYLE_EXTRACT` and
+/// `DD_TRACE_PROPAGATION_STYLE_INJECT` in the dd-trace libraries.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum PropagationStyle {
+    /// Datadog `x-datadog-*` headers.
+    Datadog,
+    /// W3C Trace Context `traceparent` / `tracestate` headers.
+    TraceContext,
+    /// Zipkin B3 multi headers (`x-b3-*`).
+    B3,
+    /// Disables propagation.
+    None,
+}
+
+impl PropagationStyle {
+    /// Parses a comma separated list of styles, such as the value of `DD_TRACE_PROPAGATION_STYLE`.
+    ///
+    /// # Errors
+    ///
+    /// If one of the styles is unknown.
+    pub fn parse_list(value: &str) -> Result<Vec<PropagationStyle>, Error> {
+        value
+            .split(',')
+            .map(str::trim)
+            .filter(|style| !style.is_empty())
+            .map(str::parse)
+            .collect()
+    }
+
+    fn fields(self) -> &'static [&'static str] {
+        match self {
+            PropagationStyle::Datadog => &[
+                DATADOG_TRACE_ID_HEADER,
+                DATADOG_PARENT_ID_HEADER,
+                DATADOG_SAMPLING_PRIORITY_HEADER,
+                DATADOG_ORIGIN_HEADER,
+                DATADOG_TAGS_HEADER,
+            ],
+            PropagationStyle::TraceContext => &[TRACEPARENT_HEADER, TRACESTATE_HEADER],
+            PropagationStyle::B3 => &[
+                B3_TRACE_ID_HEADER,
+                B3_SPAN_ID_HEADER,
+                B3_SAMPLED_HEADER,
+                B3_FLAGS_HEADER,
+            ],
+            PropagationStyle::None => &[],
+        }
+    }
+}
+
+impl FromStr for PropagationStyle {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "datadog" => Ok(PropagationStyle::Datadog),
+            "tracecontext" => Ok(PropagationStyle::TraceContext),
+            "b3" | "b3multi" => Ok(PropagationStyle::B3),
+            "none" => Ok(PropagationStyle::None),
+            _ => Err(Error::InvalidPropagationStyle(s.to_string())),
+        }
+    }
+}
+
+/// How to pick the extracted context when several styles are present but describe different traces.
+///
+/// This mirrors the behaviour of the dd-trace libraries for fleets mixing Datadog and W3C
+/// instrumentation. When all the styles agree on the trace, the first one is always used.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ExtractConflictPolicy {
+    /// Use the context of the first style in the extraction order.
+    #[default]
+    PreferFirst,
+    /// Use the Datadog context when present.
+    PreferDatadog,
+    /// Use the W3C Trace Context when present.
+    PreferW3C,
+    /// Use the first context and keep the first disagreeing one as a [`ConflictingSpanContext`]
+    /// context value, so it can be linked to the span started from the extracted context.
+    SpanLink,
+}
+
+/// The disagreeing span context kept by [`ExtractConflictPolicy::SpanLink`].
+///
+/// ## Example
+///
+/// ```
+/// use opentelemetry::{trace::Link, Context};
+/// use opentelemetry_datadog_cloudflare::ConflictingSpanContext;
+///
+/// fn links(cx: &Context) -> Vec<Link> {
+///     cx.get::<ConflictingSpanContext>()
+///         .map(|conflicting| vec![Link::new(conflicting.0.clone(), Vec::new())])
+///         .unwrap_or_default()
+/// }
+/// ```
+#[derive(Clone, Debug, PartialEq)]
+pub struct ConflictingSpanContext(pub SpanContext);
+
+/// Extracts and injects `SpanContext`s into `Extractor`s or `Injector`s using Datadog's header format.
+///
+/// The Datadog header format does not have an explicit spec, but can be divined from the client libraries,
+/// such as [dd-trace-go]
+///
+/// Other header formats can be enabled through [`DatadogPropagator::builder`], extraction tries
+/// each style in order and keeps the first valid context while injection writes every style.
+///
+/// ## Example
+///
+/// ```
+/// use opentelemetry::global;
+/// use opentelemetry_datadog_cloudflare::DatadogPropagator;
+///
+/// global::set_text_map_propagator(DatadogPropagator::default());
+/// ```
+///
+/// [dd-trace-go]: https://github.com/DataDog/dd-trace-go/blob/v1.28.0/ddtrace/tracer/textmap.go#L293
+#[derive(Clone, Debug)]
+#[allow(clippy::module_name_repetitions)]
+pub struct DatadogPropagator {
+    extract_styles: Vec<PropagationStyle>,
+    inject_styles: Vec<PropagationStyle>,
+    conflict_policy: ExtractConflictPolicy,
+    fields: Vec<String>,
+}
+
+impl Default for DatadogPropagator {
+    fn default() -> Self {
+        DatadogPropagatorBuilder::default().build()
+    }
+}
+
+/// Builder for [`DatadogPropagator`].
+///
+/// ## Example
+///
+/// ```
+/// use opentelemetry_datadog_cloudflare::{DatadogPropagator, PropagationStyle};
+///
+/// let propagator = DatadogPropagator::builder()
+///     .with_extract_styles(vec![PropagationStyle::Datadog, PropagationStyle::TraceContext])
+///     .with_inject_styles(vec![PropagationStyle::TraceContext])
+///     .build();
+/// ```
+#[derive(Clone, Debug)]
+#[allow(clippy::module_name_repetitions)]
+pub struct DatadogPropagatorBuilder {
+    extract_styles: Vec<PropagationStyle>,
+    inject_styles: Vec<PropagationStyle>,
+    conflict_policy: ExtractConflictPolicy,
+}
+
+impl Default for DatadogPropagatorBuilder {
+    fn default() -> Self {
+        DatadogPropagatorBuilder {
+            extract_styles: vec![PropagationStyle::Datadog],
+            inject_styles: vec![PropagationStyle::Datadog],
+            conflict_policy: ExtractConflictPolicy::default(),
+        }
+    }
+}
+
+impl DatadogPropagatorBuilder {
+    /// Assign the styles used for both extraction and injection, like `DD_TRACE_PROPAGATION_STYLE`
+    #[must_use]
+    pub fn with_styles(mut self, styles: Vec<PropagationStyle>) -> Self {
+        self.extract_styles = styles.clone();
+        self.inject_styles = styles;
+        self
+    }
+
+    /// Assign the styles tried in order on extraction, like `DD_TRACE_PROPAGATION_STYLE_EXTRACT`
+    #[must_use]
+    pub fn with_extract_styles(mut self, styles: Vec<PropagationStyle>) -> Self {
+        self.extract_styles = styles;
+        self
+    }
+
+    /// Assign the styles written on injection, like `DD_TRACE_PROPAGATION_STYLE_INJECT`
+    #[must_use]
+    pub fn with_inject_styles(mut self, styles: Vec<PropagationStyle>) -> Self {
+        self.inject_styles = styles;
+        self
+    }
+
+    /// Assign the policy used when the extracted styles disagree on the trace
+    #[must_use]
+    pub fn with_conflict_policy(mut self, conflict_policy: ExtractConflictPolicy) -> Self {
+        self.conflict_policy = conflict_policy;
+        self
+    }
+
+    /// Build the [`DatadogPropagator`].
+    #[must_use]
+    pub fn build(self) -> DatadogPropagator {
+        let extract_styles = normalize_styles(self.extract_styles);
+        let inject_styles = normalize_styles(self.inject_styles);
+
+        let mut fields: Vec<String> = Vec::new();
+        for style in extract_styles.iter().chain(inject_styles.iter()) {
+            for field in style.fields() {
+                if !fields.iter().any(|existing| existing == field) {
+                    fields.push((*field).to_string());
+                }
+            }
+        }
+
+        DatadogPropagator {
+            extract_styles,
+            inject_styles,
+            conflict_policy: self.conflict_policy,
+            fields,
+        }
+    }
+}
+
+/// Datadog headers only carry the lower 64 bits of the trace id.
+#[allow(clippy::cast_possible_truncation)]
+fn lower_64_bits(trace_id: TraceId) -> u64 {
+    u128::from_be_bytes(trace_id.to_bytes()) as u64
+}
+
+/// `None` disables propagation entirely, duplicates are dropped keeping the first occurrence.
+fn normalize_styles(styles: Vec<PropagationStyle>) -> Vec<PropagationStyle> {
+    if styles.contains(&PropagationStyle::None) {
+        return Vec::new();
+    }
+
+    let mut normalized = Vec::with_capacity(styles.len());
+    for style in styles {
+        if !normalized.contains(&style) {
+            normalized.push(style);
+        }
+    }
+    normalized
+}
+
+impl DatadogPropagator {
+    /// Creates a new `DatadogPropagator`.
+    #[must_use]
+    pub fn new() -> Self {
+        DatadogPropagator::default()
+    }
+
+    /// Creates a `DatadogPropagator` that only extracts Datadog headers and never injects them.
+    ///
+    /// Useful for gateways accepting Datadog context from upstream while emitting another format
+    /// downstream, e.g. combined with a W3C propagator in a `TextMapCompositePropagator`.
+    #[must_use]
+    pub fn extract_only() -> Self {
+        DatadogPropagator::builder()
+            .with_inject_styles(Vec::new())
+            .build()
+    }
+
+    /// Creates a `DatadogPropagator` that only injects Datadog headers and never extracts them.
+    #[must_use]
+    pub fn inject_only() -> Self {
+        DatadogPropagator::builder()
+            .with_extract_styles(Vec::new())
+            .build()
+    }
+
+    /// Names of the headers this propagator reads or writes, e.g. to allowlist or strip them
+    /// in a proxy.
+    #[must_use]
+    pub fn headers(&self) -> &[String] {
+        &self.fields
+    }
+
+    /// Creates a [`DatadogPropagatorBuilder`] to choose the propagation styles.
+    #[must_use]
+    pub fn builder() -> DatadogPropagatorBuilder {
+        DatadogPropagatorBuilder::default()
+    }
+
+    fn extract_trace_id(trace_id: &str) -> Result<TraceId, ExtractError> {
+        trace_id
+            .parse::<u64>()
+            .map(|id| TraceId::from(u128::from(id).to_be_bytes()))
+            .map_err(|_| ExtractError::TraceId)
+    }
+
+    fn extract_span_id(span_id: &str) -> Result<SpanId, ExtractError> {
+        span_id
+            .parse::<u64>()
+            .map(|id| SpanId::from(id.to_be_bytes()))
+            .map_err(|_| ExtractError::SpanId)
+    }
+
+    fn extract_sampling_priority(
+        sampling_priority: &str,
+    ) -> Result<SamplingPriority, ExtractError> {
+        let i = sampling_priority
+            .parse::<i32>()
+            .map_err(|_| ExtractError::SamplingPriority)?;
+
+        SamplingPriority::from_i32(i).ok_or(ExtractError::SamplingPriority)
+    }
+
+    fn extract_span_context(extractor: &dyn Extractor) -> Result<SpanContext, ExtractError> {
+        let trace_id =
+            Self::extract_trace_id(extractor.get(DATADOG_TRACE_ID_HEADER).unwrap_or(""))?;
+        // If we have a trace_id but can't get the parent span, we default it to invalid instead of completely erroring
+        // out so that the rest of the spans aren't completely lost
+        let span_id = Self::extract_span_id(extractor.get(DATADOG_PARENT_ID_HEADER).unwrap_or(""))
+            .unwrap_or(SpanId::INVALID);
+        let sampling_priority = Self::extract_sampling_priority(
+            extractor
+                .get(DATADOG_SAMPLING_PRIORITY_HEADER)
+                .unwrap_or(""),
+        );
+        let sampled = match sampling_priority {
+            Ok(priority) => priority.trace_flags(),
+            // Treat the sampling as DEFERRED instead of erroring on extracting the span context
+            Err(_) => TRACE_FLAG_DEFERRED,
+        };
+
+        // Manual decisions can't be told apart from automatic ones with the trace flags alone,
+        // record them so they are re-injected as is.
+        let trace_state = match sampling_priority {
+            Ok(priority) if priority.is_manual() => {
+                trace_state_with_sampling_priority(&TraceState::default(), priority)
+            }
+            _ => TraceState::default(),
+        };
+        // Kept with the trace so the exporter tags its chunks with them.
+        let trace_state = match extractor.get(DATADOG_TAGS_HEADER) {
+            Some(tags) => trace_state_with_propagated_tags(&trace_state, tags),
+            None => trace_state,
+        };
+        let trace_state = match extractor.get(DATADOG_ORIGIN_HEADER) {
+            Some(origin) => trace_state_with_origin(&trace_state, origin),
+            None => trace_state,
+        };
+
+        Ok(SpanContext::new(
+            trace_id,
+            span_id,
+            sampled,
+            true,
+            trace_state,
+        ))
+    }
+
+    fn extract_trace_context(extractor: &dyn Extractor) -> Result<SpanContext, ExtractError> {
+        let context = TraceContextPropagator::new().extract(extractor);
+        let span_context = context.span().span_context().clone();
+
+        if span_context.is_valid() {
+            Ok(span_context)
+        } else {
+            Err(ExtractError::TraceId)
+        }
+    }
+
+    /// Keeps the vendor entries of an incoming `tracestate` when the `traceparent` next to it
+    /// describes the same trace, so they survive re-injection further down the request path.
+    /// What the Datadog headers carried wins over the `dd` member of the `tracestate`.
+    fn with_w3c_trace_state(span_context: SpanContext, extractor: &dyn Extractor) -> SpanContext {
+        if extractor.get(TRACESTATE_HEADER).is_none() {
+            return span_context;
+        }
+
+        match Self::extract_trace_context(extractor) {
+            Ok(w3c)
+                if lower_64_bits(w3c.trace_id()) == lower_64_bits(span_context.trace_id())
+                    && w3c.trace_state() != span_context.trace_state() =>
+            {
+                let trace_state =
+                    match sampling_priority_from_trace_state(span_context.trace_state()) {
+                        Some(priority) => {
+                            trace_state_with_sampling_priority(w3c.trace_state(), priority)
+                        }
+                        None => w3c.trace_state().clone(),
+                    };
+                let propagated_tags = propagated_tags_from_trace_state(span_context.trace_state());
+                let trace_state = if propagated_tags.is_empty() {
+                    trace_state
+                } else {
+                    let header: Vec<String> = propagated_tags
+                        .into_iter()
+                        .map(|(key, value)| format!("{key}={value}"))
+                        .collect();
+                    trace_state_with_propagated_tags(&trace_state, &header.join(","))
+                };
+                let trace_state = match origin_from_trace_state(span_context.trace_state()) {
+                    Some(origin) => trace_state_with_origin(&trace_state, &origin),
+                    None => trace_state,
+                };
+
+                SpanContext::new(
+                    span_context.trace_id(),
+                    span_context.span_id(),
+                    span_context.trace_flags(),
+                    span_context.is_remote(),
+                    trace_state,
+                )
+            }
+            _ => span_context,
+        }
+    }
+
+    fn extract_b3(extractor: &dyn Extractor) -> Result<SpanContext, ExtractError> {
+        let trace_id = TraceId::from_hex(extractor.get(B3_TRACE_ID_HEADER).unwrap_or(""))
+            .map_err(|_| ExtractError::TraceId)?;
+        let span_id = SpanId::from_hex(extractor.get(B3_SPAN_ID_HEADER).unwrap_or(""))
+            .map_err(|_| ExtractError::SpanId)?;
+
+        // The debug flag implies an accept decision.
+        let sampled = if extractor.get(B3_FLAGS_HEADER) == Some("1") {
+            TraceFlags::SAMPLED
+        } else {
+            match extractor.get(B3_SAMPLED_HEADER) {
+                Some("1" | "true") => TraceFlags::SAMPLED,
+                Some("0" | "false") => TraceFlags::default(),
+                _ => TRACE_FLAG_DEFERRED,
+            }
+        };
+
+        Ok(SpanContext::new(
+            trace_id,
+            span_id,
+            sampled,
+            true,
+            TraceState::default(),
+        ))
+    }
+
+    fn extract_with_style(
+        style: PropagationStyle,
+        extractor: &dyn Extractor,
+    ) -> Result<SpanContext, ExtractError> {
+        match style {
+            PropagationStyle::Datadog => Self::extract_span_context(extractor),
+            PropagationStyle::TraceContext => Self::extract_trace_context(extractor),
+            PropagationStyle::B3 => Self::extract_b3(extractor),
+            PropagationStyle::None => Err(ExtractError::TraceId),
+        }
+    }
+
+    fn inject_datadog(span_context: &SpanContext, injector: &mut dyn Injector) {
+        injector.set(
+            DATADOG_TRACE_ID_HEADER,
+            lower_64_bits(span_context.trace_id()).to_string(),
+        );
+        injector.set(
+            DATADOG_PARENT_ID_HEADER,
+            u64::from_be_bytes(span_context.span_id().to_bytes()).to_string(),
+        );
+
+        let sampling_priority = sampling_priority_from_trace_state(span_context.trace_state())
+            .filter(|priority| priority.is_manual())
+            .or_else(|| {
+                if span_context.trace_flags() & TRACE_FLAG_DEFERRED == TRACE_FLAG_DEFERRED {
+                    None
+                } else if span_context.is_sampled() {
+                    Some(SamplingPriority::AutoKeep)
+                } else {
+                    Some(SamplingPriority::AutoReject)
+                }
+            });
+
+        if let Some(sampling_priority) = sampling_priority {
+            injector.set(
+                DATADOG_SAMPLING_PRIORITY_HEADER,
+                (sampling_priority as i32).to_string(),
+            );
+        }
+        if let Some(origin) = origin_from_trace_state(span_context.trace_state()) {
+            injector.set(DATADOG_ORIGIN_HEADER, origin);
+        }
+        let propagated_tags = propagated_tags_from_trace_state(span_context.trace_state());
+        if !propagated_tags.is_empty() {
+            let tags: Vec<String> = propagated_tags
+                .into_iter()
+                .map(|(key, value)| format!("{key}={value}"))
+                .collect();
+            injector.set(DATADOG_TAGS_HEADER, tags.join(","));
+        }
+    }
+
+    fn inject_b3(span_context: &SpanContext, injector: &mut dyn Injector) {
+        injector.set(
+            B3_TRACE_ID_HEADER,
+            format!(
+                "{:032x}",
+                u128::from_be_bytes(span_context.trace_id().to_bytes())
+            ),
+        );
+        injector.set(
+            B3_SPAN_ID_HEADER,
+            format!(
+                "{:016x}",
+                u64::from_be_bytes(span_context.span_id().to_bytes())
+            ),
+        );
+
+        if span_context.trace_flags() & TRACE_FLAG_DEFERRED != TRACE_FLAG_DEFERRED {
+            let sampled = if span_context.is_sampled() { "1" } else { "0" };
+            injector.set(B3_SAMPLED_HEADER, sampled.to_string());
+        }
+    }
+}
+
+impl TextMapPropagator for DatadogPropagator {
+    fn inject_context(&self, cx: &Context, injector: &mut dyn Injector) {
+        let span = cx.span();
+        let span_context = span.span_context();
+        if span_context.is_valid() {
+            for style in &self.inject_styles {
+                match style {
+                    PropagationStyle::Datadog => Self::inject_datadog(span_context, injector),
+                    PropagationStyle::TraceContext => {
+                        TraceContextPropagator::new().inject_context(cx, injector);
+                    }
+                    PropagationStyle::B3 => Self::inject_b3(span_context, injector),
+                    PropagationStyle::None => {}
+                }
+            }
+        }
+    }
+
+    fn extract_with_context(&self, cx: &Context, extractor: &dyn Extractor) -> Context {
+        let mut candidates = self.extract_styles.iter().filter_map(|style| {
+            Self::extract_with_style(*style, extractor)
+                .ok()
+                .map(|span_context| (*style, span_context))
+        });
+
+        let (first_style, first) = match candidates.next() {
+            Some(candidate) => candidate,
+            None => return cx.with_remote_span_context(SpanContext::empty_context()),
+        };
+
+        let (extracted, conflicting) = if self.conflict_policy == ExtractConflictPolicy::PreferFirst
+        {
+            (first, None)
+        } else {
+            let trace_id = lower_64_bits(first.trace_id());
+            let conflicting: Vec<(PropagationStyle, SpanContext)> = candidates
+                .filter(|(_, span_context)| lower_64_bits(span_context.trace_id()) != trace_id)
+                .collect();
+
+            let preferred = match self.conflict_policy {
+                ExtractConflictPolicy::PreferDatadog => Some(PropagationStyle::Datadog),
+                ExtractConflictPolicy::PreferW3C => Some(PropagationStyle::TraceContext),
+                ExtractConflictPolicy::PreferFirst | ExtractConflictPolicy::SpanLink => None,
+            };
+
+            match preferred {
+                Some(preferred) if first_style != preferred => {
+                    match conflicting
+                        .into_iter()
+                        .find(|(style, _)| *style == preferred)
+                    {
+                        Some((_, span_context)) => (span_context, None),
+                        None => (first, None),
+                    }
+                }
+                Some(_) => (first, None),
+                None => (
+                    first,
+                    conflicting
+                        .into_iter()
+                        .next()
+                        .map(|(_, span_context)| ConflictingSpanContext(span_context)),
+                ),
+            }
+        };
+
+        let cx = cx.with_remote_span_context(Self::with_w3c_trace_state(extracted, extractor));
+        match conflicting {
+            Some(conflicting) => cx.with_value(conflicting),
+            None => cx,
+        }
+    }
+
+    fn fields(&self) -> FieldIter<'_> {
+        FieldIter::new(self.fields.as_slice())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use opentelemetry::testing::trace::TestSpan;
+    use opentelemetry::trace::TraceState;
+    use std::collections::HashMap;
+
+    #[rustfmt::skip]
+    fn extract_test_data() -> Vec<(Vec<(&'static str, &'static str)>, SpanContext)> {
+        vec![
+            (vec![], SpanContext::empty_context()),
+            (vec![(DATADOG_SAMPLING_PRIORITY_HEADER, "0")], SpanContext::empty_context()),
+            (vec![(DATADOG_TRACE_ID_HEADER, "garbage")], SpanContext::empty_context()),
+            (vec![(DATADOG_TRACE_ID_HEADER, "1234"), (DATADOG_PARENT_ID_HEADER, "garbage")], SpanContext::new(TraceId::from_u128(1234), SpanId::INVALID, TRACE_FLAG_DEFERRED, true, TraceState::default())),
+            (vec![(DATADOG_TRACE_ID_HEADER, "1234"), (DATADOG_PARENT_ID_HEADER, "12")], SpanContext::new(TraceId::from_u128(1234), SpanId::from_u64(12), TRACE_FLAG_DEFERRED, true, TraceState::default())),
+            (vec![(DATADOG_TRACE_ID_HEADER, "1234"), (DATADOG_PARENT_ID_HEADER, "12"), (DATADOG_SAMPLING_PRIORITY_HEADER, "0")], SpanContext::new(TraceId::from_u128(1234), SpanId::from_u64(12), TraceFlags::default(), true, TraceState::default())),
+            (vec![(DATADOG_TRACE_ID_HEADER, "1234"), (DATADOG_PARENT_ID_HEADER, "12"), (DATADOG_SAMPLING_PRIORITY_HEADER, "1")], SpanContext::new(TraceId::from_u128(1234), SpanId::from_u64(12), TraceFlags::SAMPLED, true, TraceState::default())),
+        ]
+    }
+
+    #[rustfmt::skip]
+    fn inject_test_data() -> Vec<(Vec<(&'static str, &'static str)>, SpanContext)> {
+        vec![
+            (vec![], SpanContext::empty_context()),
+            (vec![], SpanContext::new(TraceId::INVALID, SpanId::INVALID, TRACE_FLAG_DEFERRED, true, TraceState::default())),
+            (vec![], SpanContext::new(TraceId::from_hex("1234").unwrap(), SpanId::INVALID, TRACE_FLAG_DEFERRED, true, TraceState::default())),
+            (vec![], SpanContext::new(TraceId::from_hex("1234").unwrap(), SpanId::INVALID, TraceFlags::SAMPLED, true, TraceState::default())),
+            (vec![(DATADOG_TRACE_ID_HEADER, "1234"), (DATADOG_PARENT_ID_HEADER, "12")], SpanContext::new(TraceId::from_u128(1234), SpanId::from_u64(12), TRACE_FLAG_DEFERRED, true, TraceState::default())),
+            (vec![(DATADOG_TRACE_ID_HEADER, "1234"), (DATADOG_PARENT_ID_HEADER, "12"), (DATADOG_SAMPLING_PRIORITY_HEADER, "0")], SpanContext::new(TraceId::from_u128(1234), SpanId::from_u64(12), TraceFlags::default(), true, TraceState::default())),
+            (vec![(DATADOG_TRACE_ID_HEADER, "1234"), (DATADOG_PARENT_ID_HEADER, "12"), (DATADOG_SAMPLING_PRIORITY_HEADER, "1")], SpanContext::new(TraceId::from_u128(1234), SpanId::from_u64(12), TraceFlags::SAMPLED, true, TraceState::default())),
+        ]
+    }
+
+    #[test]
+    fn test_extract() {
+        for (header_list, expected) in extract_test_data() {
+            let map: HashMap<String, String> = header_list
+                .into_iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect();
+
+            let propagator = DatadogPropagator::default();
+            let context = propagator.extract(&map);
+            assert_eq!(context.span().span_context(), &expected);
+        }
+    }
+
+    #[test]
+    fn test_extract_empty() {
+        let map: HashMap<String, String> = HashMap::new();
+        let propagator = DatadogPropagator::default();
+        let context = propagator.extract(&map);
+        assert_eq!(context.span().span_context(), &SpanContext::empty_context());
+    }
+
+    #[test]
+    fn test_inject() {
+        let propagator = DatadogPropagator::default();
+        for (header_values, span_context) in inject_test_data() {
+            let mut injector: HashMap<String, String> = HashMap::new();
+            propagator.inject_context(
+                &Context::current_with_span(TestSpan(span_context)),
+                &mut injector,
+            );
+
+            if !header_values.is_empty() {
+                for (k, v) in header_values {
+                    let injected_value: Option<&String> = injector.get(k);
+                    assert_eq!(injected_value, Some(&v.to_string()));
+                    injector.remove(k);
+                }
+            }
+            assert!(injector.is_empty());
+        }
+    }
+
+    fn header_map(header_list: Vec<(&'static str, &'static str)>) -> HashMap<String, String> {
+        header_list
+            .into_iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn test_parse_styles() {
+        assert_eq!(
+            PropagationStyle::parse_list("Datadog, tracecontext,b3multi").unwrap(),
+            vec![
+                PropagationStyle::Datadog,
+                PropagationStyle::TraceContext,
+                PropagationStyle::B3
+            ]
+        );
+        assert!(PropagationStyle::parse_list("datadog,jaeger").is_err());
+    }
+
+    #[test]
+    fn test_extract_styles_in_order() {
+        let map = header_map(vec![
+            (DATADOG_TRACE_ID_HEADER, "1234"),
+            (DATADOG_PARENT_ID_HEADER, "12"),
+            (DATADOG_SAMPLING_PRIORITY_HEADER, "1"),
+            (
+                TRACEPARENT_HEADER,
+                "00-000000000000000000000000000004d2-000000000000000c-01",
+            ),
+            (B3_TRACE_ID_HEADER, "00000000000000000000000000000001"),
+            (B3_SPAN_ID_HEADER, "0000000000000002"),
+        ]);
+
+        let propagator = DatadogPropagator::builder()
+            .with_extract_styles(vec![PropagationStyle::B3, PropagationStyle::Datadog])
+            .build();
+        let context = propagator.extract(&map);
+        assert_eq!(
+            context.span().span_context(),
+            &SpanContext::new(
+                TraceId::from_u128(1),
+                SpanId::from_u64(2),
+                TRACE_FLAG_DEFERRED,
+                true,
+                TraceState::default()
+            )
+        );
+
+        let propagator = DatadogPropagator::builder()
+            .with_extract_styles(vec![PropagationStyle::TraceContext])
+            .build();
+        let context = propagator.extract(&map);
+        assert_eq!(
+            context.span().span_context().trace_id(),
+            TraceId::from_u128(1234)
+        );
+        assert_eq!(
+            context.span().span_context().span_id(),
+            SpanId::from_u64(12)
+        );
+        assert!(context.span().span_context().is_sampled());
+    }
+
+    #[test]
+    fn test_extract_falls_back_to_next_style() {
+        let map = header_map(vec![
+            (B3_TRACE_ID_HEADER, "00000000000000000000000000000001"),
+            (B3_SPAN_ID_HEADER, "0000000000000002"),
+            (B3_SAMPLED_HEADER, "0"),
+        ]);
+
+        let propagator = DatadogPropagator::builder()
+            .with_extract_styles(vec![PropagationStyle::Datadog, PropagationStyle::B3])
+            .build();
+        let context = propagator.extract(&map);
+        assert_eq!(
+            context.span().span_context(),
+            &SpanContext::new(
+                TraceId::from_u128(1),
+                SpanId::from_u64(2),
+                TraceFlags::default(),
+                true,
+                TraceState::default()
+            )
+        );
+    }
+
+    #[test]
+    fn test_inject_styles() {
+        let propagator = DatadogPropagator::builder()
+            .with_inject_styles(vec![PropagationStyle::TraceContext, PropagationStyle::B3])
+            .build();
+        let span_context = SpanContext::new(
+            TraceId::from_u128(1234),
+            SpanId::from_u64(12),
+            TraceFlags::SAMPLED,
+            true,
+            TraceState::default(),
+        );
+        let mut injector: HashMap<String, String> = HashMap::new();
+        propagator.inject_context(
+            &Context::current_with_span(TestSpan(span_context)),
+            &mut injector,
+        );
+
+        assert_eq!(injector.get(DATADOG_TRACE_ID_HEADER), None);
+        assert_eq!(
+            injector.get(TRACEPARENT_HEADER).map(String::as_str),
+            Some("00-000000000000000000000000000004d2-000000000000000c-01")
+        );
+        assert_eq!(
+            injector.get(B3_TRACE_ID_HEADER).map(String::as_str),
+            Some("000000000000000000000000000004d2")
+        );
+        assert_eq!(
+            injector.get(B3_SPAN_ID_HEADER).map(String::as_str),
+            Some("000000000000000c")
+        );
+        assert_eq!(
+            injector.get(B3_SAMPLED_HEADER).map(String::as_str),
+            Some("1")
+        );
+    }
+
+    #[test]
+    fn test_none_style_disables_propagation() {
+        let propagator = DatadogPropagator::builder()
+            .with_styles(vec![PropagationStyle::Datadog, PropagationStyle::None])
+            .build();
+        let span_context = SpanContext::new(
+            TraceId::from_u128(1234),
+            SpanId::from_u64(12),
+            TraceFlags::SAMPLED,
+            true,
+            TraceState::default(),
+        );
+        let mut injector: HashMap<String, String> = HashMap::new();
+        propagator.inject_context(
+            &Context::current_with_span(TestSpan(span_context)),
+            &mut injector,
+        );
+        assert!(injector.is_empty());
+
+        let map = header_map(vec![
+            (DATADOG_TRACE_ID_HEADER, "1234"),
+            (DATADOG_PARENT_ID_HEADER, "12"),
+        ]);
+        let context = propagator.extract(&map);
+        assert_eq!(context.span().span_context(), &SpanContext::empty_context());
+        assert_eq!(propagator.fields().count(), 0);
+    }
+
+    #[test]
+    fn test_extract_keeps_w3c_trace_state() {
+        let map = header_map(vec![
+            (DATADOG_TRACE_ID_HEADER, "1234"),
+            (DATADOG_PARENT_ID_HEADER, "12"),
+            (DATADOG_SAMPLING_PRIORITY_HEADER, "1"),
+            (
+                TRACEPARENT_HEADER,
+                "00-000000000000000000000000000004d2-000000000000000c-01",
+            ),
+            (TRACESTATE_HEADER, "dd=s:1,congo=t61rcWkgMzE"),
+        ]);
+
+        let propagator = DatadogPropagator::default();
+        let context = propagator.extract(&map);
+        let trace_state = context.span().span_context().trace_state().clone();
+        assert_eq!(trace_state.get("congo"), Some("t61rcWkgMzE"));
+        assert_eq!(trace_state.get("dd"), Some("s:1"));
+    }
+
+    #[test]
+    fn test_extract_merges_datadog_origin_into_w3c_trace_state() {
+        let map = header_map(vec![
+            (DATADOG_TRACE_ID_HEADER, "1234"),
+            (DATADOG_PARENT_ID_HEADER, "12"),
+            (DATADOG_SAMPLING_PRIORITY_HEADER, "2"),
+            (DATADOG_ORIGIN_HEADER, "synthetics"),
+            (
+                TRACEPARENT_HEADER,
+                "00-000000000000000000000000000004d2-000000000000000c-01",
+            ),
+            (TRACESTATE_HEADER, "dd=s:1;o:rum,congo=t61rcWkgMzE"),
+        ]);
+
+        let propagator = DatadogPropagator::default();
+        let context = propagator.extract(&map);
+        let trace_state = context.span().span_context().trace_state().clone();
+        assert_eq!(trace_state.get("congo"), Some("t61rcWkgMzE"));
+        assert_eq!(trace_state.get("dd"), Some("s:2;o:synthetics"));
+        assert_eq!(
+            origin_from_trace_state(&trace_state),
+            Some("synthetics".to_string())
+        );
+        assert_eq!(
+            sampling_priority_from_trace_state(&trace_state),
+            Some(SamplingPriority::UserKeep)
+        );
+    }
+
+    #[test]
+    fn test_extract_merges_datadog_tags_into_w3c_trace_state() {
+        let map = header_map(vec![
+            (DATADOG_TRACE_ID_HEADER, "1234"),
+            (DATADOG_PARENT_ID_HEADER, "12"),
+            (DATADOG_SAMPLING_PRIORITY_HEADER, "1"),
+            (DATADOG_TAGS_HEADER, "_dd.p.dm=-4,_dd.p.usr.id=dXNlcg=="),
+            (
+                TRACEPARENT_HEADER,
+                "00-000000000000000000000000000004d2-000000000000000c-01",
+            ),
+            (TRACESTATE_HEADER, "dd=s:1;t.dm:-0,congo=t61rcWkgMzE"),
+        ]);
+
+        let propagator = DatadogPropagator::default();
+        let context = propagator.extract(&map);
+        let trace_state = context.span().span_context().trace_state().clone();
+        assert_eq!(trace_state.get("congo"), Some("t61rcWkgMzE"));
+        assert_eq!(
+            propagated_tags_from_trace_state(&trace_state),
+            vec![
+                ("_dd.p.dm".to_string(), "-4".to_string()),
+                ("_dd.p.usr.id".to_string(), "dXNlcg==".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_extract_ignores_trace_state_of_other_trace() {
+        let map = header_map(vec![
+            (DATADOG_TRACE_ID_HEADER, "1234"),
+            (DATADOG_PARENT_ID_HEADER, "12"),
+            (
+                TRACEPARENT_HEADER,
+                "00-00000000000000000000000000000001-000000000000000c-01",
+            ),
+            (TRACESTATE_HEADER, "congo=t61rcWkgMzE"),
+        ]);
+
+        let propagator = DatadogPropagator::default();
+        let context = propagator.extract(&map);
+        assert_eq!(
+            context.span().span_context().trace_state(),
+            &TraceState::default()
+        );
+    }
+
+    #[test]
+    fn test_user_priority_round_trip() {
+        let propagator = DatadogPropagator::default();
+        for priority in ["2", "-1"] {
+            let map = header_map(vec![
+                (DATADOG_TRACE_ID_HEADER, "1234"),
+                (DATADOG_PARENT_ID_HEADER, "12"),
+                (DATADOG_SAMPLING_PRIORITY_HEADER, priority),
+            ]);
+            let context = propagator.extract(&map);
+
+            let mut injector: HashMap<String, String> = HashMap::new();
+            propagator.inject_context(&context, &mut injector);
+            assert_eq!(
+                injector
+                    .get(DATADOG_SAMPLING_PRIORITY_HEADER)
+                    .map(String::as_str),
+                Some(priority)
+            );
+        }
+    }
+
+    #[test]
+    fn test_origin_round_trip() {
+        let propagator = DatadogPropagator::default();
+        let map = header_map(vec![
+            (DATADOG_TRACE_ID_HEADER, "1234"),
+            (DATADOG_PARENT_ID_HEADER, "12"),
+            (DATADOG_SAMPLING_PRIORITY_HEADER, "1"),
+            (DATADOG_ORIGIN_HEADER, "synthetics"),
+        ]);
+        let context = propagator.extract(&map);
+
+        let mut injector: HashMap<String, String> = HashMap::new();
+        propagator.inject_context(&context, &mut injector);
+        assert_eq!(
+            injector.get(DATADOG_ORIGIN_HEADER).map(String::as_str),
+            Some("synthetics")
+        );
+    }
+
+    #[test]
+    fn test_propagated_tags_round_trip() {
+        let propagator = DatadogPropagator::default();
+        let map = header_map(vec![
+            (DATADOG_TRACE_ID_HEADER, "1234"),
+            (DATADOG_PARENT_ID_HEADER, "12"),
+            (DATADOG_SAMPLING_PRIORITY_HEADER, "1"),
+            (DATADOG_TAGS_HEADER, "_dd.p.dm=-4,_dd.p.usr.id=dXNlcg=="),
+        ]);
+        let context = propagator.extract(&map);
+
+        let mut injector: HashMap<String, String> = HashMap::new();
+        propagator.inject_context(&context, &mut injector);
+        assert_eq!(
+            injector.get(DATADOG_TAGS_HEADER).map(String::as_str),
+            Some("_dd.p.dm=-4,_dd.p.usr.id=dXNlcg==")
+        );
+    }
+
+    #[test]
+    fn test_manual_keep() {
+        let propagator = DatadogPropagator::default();
+        let span_context = SpanContext::new(
+            TraceId::from_u128(1234),
+            SpanId::from_u64(12),
+            TraceFlags::default(),
+            false,
+            TraceState::default(),
+        );
+        let context = with_sampling_priority(
+            &Context::current_with_span(TestSpan(span_context)),
+            SamplingPriority::UserKeep,
+        );
+        assert!(context.span().span_context().is_sampled());
+
+        let mut injector: HashMap<String, String> = HashMap::new();
+        propagator.inject_context(&context, &mut injector);
+        assert_eq!(
+            injector
+                .get(DATADOG_SAMPLING_PRIORITY_HEADER)
+                .map(String::as_str),
+            Some("2")
+        );
+    }
+
+    fn conflicting_headers() -> HashMap<String, String> {
+        header_map(vec![
+            (DATADOG_TRACE_ID_HEADER, "1234"),
+            (DATADOG_PARENT_ID_HEADER, "12"),
+            (DATADOG_SAMPLING_PRIORITY_HEADER, "1"),
+            (
+                TRACEPARENT_HEADER,
+                "00-00000000000000000000000000000001-0000000000000002-01",
+            ),
+        ])
+    }
+
+    #[test]
+    fn test_conflict_policies() {
+        let map = conflicting_headers();
+        let styles = vec![PropagationStyle::Datadog, PropagationStyle::TraceContext];
+
+        for (policy, expected_trace_id) in [
+            (ExtractConflictPolicy::PreferFirst, 1234),
+            (ExtractConflictPolicy::PreferDatadog, 1234),
+            (ExtractConflictPolicy::PreferW3C, 1),
+            (ExtractConflictPolicy::SpanLink, 1234),
+        ] {
+            let propagator = DatadogPropagator::builder()
+                .with_extract_styles(styles.clone())
+                .with_conflict_policy(policy)
+                .build();
+            let context = propagator.extract(&map);
+            assert_eq!(
+                context.span().span_context().trace_id(),
+                TraceId::from_u128(expected_trace_id)
+            );
+        }
+    }
+
+    #[test]
+    fn test_conflict_span_link() {
+        let propagator = DatadogPropagator::builder()
+            .with_extract_styles(vec![
+                PropagationStyle::Datadog,
+                PropagationStyle::TraceContext,
+            ])
+            .with_conflict_policy(ExtractConflictPolicy::SpanLink)
+            .build();
+
+        let context = propagator.extract(&conflicting_headers());
+        let conflicting = context.get::<ConflictingSpanContext>().unwrap();
+        assert_eq!(conflicting.0.trace_id(), TraceId::from_u128(1));
+        assert_eq!(conflicting.0.span_id(), SpanId::from_u64(2));
+
+        let context = propagator.extract(&header_map(vec![
+            (DATADOG_TRACE_ID_HEADER, "1234"),
+            (DATADOG_PARENT_ID_HEADER, "12"),
+        ]));
+        assert!(context.get::<ConflictingSpanContext>().is_none());
+    }
+
+    #[test]
+    fn test_headers() {
+        let propagator = DatadogPropagator::builder()
+            .with_extract_styles(vec![
+                PropagationStyle::Datadog,
+                PropagationStyle::TraceContext,
+            ])
+            .with_inject_styles(vec![PropagationStyle::TraceContext])
+            .build();
+
+        assert_eq!(
+            propagator.headers(),
+            &[
+                DATADOG_TRACE_ID_HEADER,
+                DATADOG_PARENT_ID_HEADER,
+                DATADOG_SAMPLING_PRIORITY_HEADER,
+                DATADOG_ORIGIN_HEADER,
+                DATADOG_TAGS_HEADER,
+                TRACEPARENT_HEADER,
+                TRACESTATE_HEADER,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_extract_only_and_inject_only() {
+        let map = header_map(vec![
+            (DATADOG_TRACE_ID_HEADER, "1234"),
+            (DATADOG_PARENT_ID_HEADER, "12"),
+        ]);
+        let span_context = SpanContext::new(
+            TraceId::from_u128(1234),
+            SpanId::from_u64(12),
+            TraceFlags::SAMPLED,
+            true,
+            TraceState::default(),
+        );
+
+        let propagator = DatadogPropagator::extract_only();
+        assert!(propagator.extract(&map).span().span_context().is_valid());
+        let mut injector: HashMap<String, String> = HashMap::new();
+        propagator.inject_context(
+            &Context::current_with_span(TestSpan(span_context.clone())),
+            &mut injector,
+        );
+        assert!(injector.is_empty());
+
+        let propagator = DatadogPropagator::inject_only();
+        assert!(!propagator.extract(&map).span().span_context().is_valid());
+        propagator.inject_context(
+            &Context::current_with_span(TestSpan(span_context)),
+            &mut injector,
+        );
+        assert_eq!(
+            injector.get(DATADOG_TRACE_ID_HEADER).map(String::as_str),
+            Some("1234")
+        );
+    }
+}
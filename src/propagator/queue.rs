@@ -0,0 +1,89 @@
+use std::collections::HashMap;
+
+use opentelemetry::{global, Context};
+use serde::{Deserialize, Serialize};
+
+/// A Cloudflare Queues message body wrapped with the propagation headers of the producer.
+///
+/// The headers are written by the global text map propagator, so installing a
+/// [`DatadogPropagator`](crate::DatadogPropagator) makes the consumer continue the trace with
+/// Datadog compatible ids. The headers are stored under `_datadog`, like the dd-trace
+/// integrations for other queueing systems do.
+///
+/// ## Example
+///
+/// ```no_run
+/// use opentelemetry::Context;
+/// use opentelemetry_datadog_cloudflare::TracedMessage;
+///
+/// async fn produce(queue: &worker::Queue, job: String) -> worker::Result<()> {
+///     queue.send(&TracedMessage::new(&Context::current(), job)).await
+/// }
+///
+/// fn consume(message: TracedMessage<String>) {
+///     let (parent_cx, job) = message.into_parts();
+///     // start the consumer span with `parent_cx` as parent.
+/// }
+/// ```
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TracedMessage<T> {
+    #[serde(rename = "_datadog", default)]
+    headers: HashMap<String, String>,
+    body: T,
+}
+
+impl<T> TracedMessage<T> {
+    /// Wrap `body` with the span context of `cx`.
+    #[must_use]
+    pub fn new(cx: &Context, body: T) -> Self {
+        let mut headers = HashMap::new();
+        global::get_text_map_propagator(|propagator| propagator.inject_context(cx, &mut headers));
+
+        TracedMessage { headers, body }
+    }
+
+    /// The message body.
+    #[must_use]
+    pub fn body(&self) -> &T {
+        &self.body
+    }
+
+    /// Extract the producer context and unwrap the body.
+    #[must_use]
+    pub fn into_parts(self) -> (Context, T) {
+        let cx = global::get_text_map_propagator(|propagator| propagator.extract(&self.headers));
+
+        (cx, self.body)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::DatadogPropagator;
+    use opentelemetry::testing::trace::TestSpan;
+    use opentelemetry::trace::{
+        SpanContext, SpanId, TraceContextExt, TraceFlags, TraceId, TraceState,
+    };
+
+    #[test]
+    fn test_message_round_trip() {
+        global::set_text_map_propagator(DatadogPropagator::default());
+
+        let span_context = SpanContext::new(
+            TraceId::from_u128(1234),
+            SpanId::from_u64(12),
+            TraceFlags::SAMPLED,
+            true,
+            TraceState::default(),
+        );
+        let message = TracedMessage::new(
+            &Context::current_with_span(TestSpan(span_context.clone())),
+            "job",
+        );
+
+        let (cx, body) = message.into_parts();
+        assert_eq!(body, "job");
+        assert_eq!(cx.span().span_context(), &span_context);
+    }
+}
@@ -0,0 +1,277 @@
+use opentelemetry::{
+    trace::{SpanContext, TraceContextExt, TraceFlags, TraceState},
+    Context,
+};
+
+/// `tracestate` list member used by Datadog to carry its own propagation tags.
+const DATADOG_TRACE_STATE_KEY: &str = "dd";
+const SAMPLING_PRIORITY_TAG: &str = "s:";
+/// Prefix of the propagated trace tags in the `dd` member, `t.dm` standing for `_dd.p.dm`.
+const PROPAGATED_TAG_PREFIX: &str = "t.";
+const PROPAGATED_TAG_KEY_PREFIX: &str = "_dd.p.";
+const ORIGIN_TAG: &str = "o:";
+
+/// Datadog sampling priority, as carried by the `x-datadog-sampling-priority` header.
+///
+/// `Auto*` priorities are decided by samplers and map to the sampled trace flag, while the
+/// `User*` ones are manual decisions which are recorded in the span context `TraceState` so they
+/// are re-injected unchanged.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SamplingPriority {
+    /// The application asked to drop the trace.
+    UserReject = -1,
+    /// The sampler decided to drop the trace.
+    AutoReject = 0,
+    /// The sampler decided to keep the trace.
+    AutoKeep = 1,
+    /// The application asked to keep the trace.
+    UserKeep = 2,
+}
+
+impl SamplingPriority {
+    pub(crate) fn from_i32(priority: i32) -> Option<Self> {
+        match priority {
+            -1 => Some(SamplingPriority::UserReject),
+            0 => Some(SamplingPriority::AutoReject),
+            1 => Some(SamplingPriority::AutoKeep),
+            2 => Some(SamplingPriority::UserKeep),
+            _ => None,
+        }
+    }
+
+    /// Whether the priority keeps the trace.
+    #[must_use]
+    pub fn is_keep(self) -> bool {
+        matches!(
+            self,
+            SamplingPriority::AutoKeep | SamplingPriority::UserKeep
+        )
+    }
+
+    pub(crate) fn is_manual(self) -> bool {
+        matches!(
+            self,
+            SamplingPriority::UserKeep | SamplingPriority::UserReject
+        )
+    }
+
+    pub(crate) fn trace_flags(self) -> TraceFlags {
+        if self.is_keep() {
+            TraceFlags::SAMPLED
+        } else {
+            TraceFlags::default()
+        }
+    }
+}
+
+/// Reads the sampling priority recorded in the `dd` member of `trace_state`, if any.
+pub(crate) fn sampling_priority_from_trace_state(
+    trace_state: &TraceState,
+) -> Option<SamplingPriority> {
+    trace_state
+        .get(DATADOG_TRACE_STATE_KEY)?
+        .split(';')
+        .find_map(|tag| tag.strip_prefix(SAMPLING_PRIORITY_TAG))
+        .and_then(|priority| priority.parse().ok())
+        .and_then(SamplingPriority::from_i32)
+}
+
+/// Records `priority` in the `dd` member of `trace_state`, keeping its other tags.
+pub(crate) fn trace_state_with_sampling_priority(
+    trace_state: &TraceState,
+    priority: SamplingPriority,
+) -> TraceState {
+    let mut tags: Vec<String> = trace_state
+        .get(DATADOG_TRACE_STATE_KEY)
+        .unwrap_or_default()
+        .split(';')
+        .filter(|tag| !tag.is_empty() && !tag.starts_with(SAMPLING_PRIORITY_TAG))
+        .map(ToString::to_string)
+        .collect();
+    tags.insert(0, format!("{}{}", SAMPLING_PRIORITY_TAG, priority as i32));
+
+    trace_state
+        .insert(DATADOG_TRACE_STATE_KEY, tags.join(";"))
+        .unwrap_or_else(|_| trace_state.clone())
+}
+
+/// The `_dd.p.*` tags propagated with the trace, recorded in the `dd` member of `trace_state`.
+pub(crate) fn propagated_tags_from_trace_state(trace_state: &TraceState) -> Vec<(String, String)> {
+    trace_state
+        .get(DATADOG_TRACE_STATE_KEY)
+        .unwrap_or_default()
+        .split(';')
+        .filter_map(|tag| tag.strip_prefix(PROPAGATED_TAG_PREFIX)?.split_once(':'))
+        .map(|(key, value)| {
+            (
+                format!("{PROPAGATED_TAG_KEY_PREFIX}{key}"),
+                value.replace('~', "="),
+            )
+        })
+        .collect()
+}
+
+/// Records the `_dd.p.*` tags of an `x-datadog-tags` header in the `dd` member of `trace_state`,
+/// as the Datadog tracers carry them in `tracestate`, replacing the ones it had.
+pub(crate) fn trace_state_with_propagated_tags(
+    trace_state: &TraceState,
+    header: &str,
+) -> TraceState {
+    let propagated: Vec<String> = header
+        .split(',')
+        .filter_map(|tag| tag.trim().split_once('='))
+        .filter_map(|(key, value)| Some((key.strip_prefix(PROPAGATED_TAG_KEY_PREFIX)?, value)))
+        .filter(|(key, value)| {
+            !key.is_empty()
+                && !key.contains([';', ':', '~'])
+                && !value.contains([';', '~'])
+                && key
+                    .chars()
+                    .chain(value.chars())
+                    .all(|c| c.is_ascii_graphic())
+        })
+        .map(|(key, value)| format!("{PROPAGATED_TAG_PREFIX}{key}:{}", value.replace('=', "~")))
+        .collect();
+    if propagated.is_empty() {
+        return trace_state.clone();
+    }
+
+    let mut tags: Vec<String> = trace_state
+        .get(DATADOG_TRACE_STATE_KEY)
+        .unwrap_or_default()
+        .split(';')
+        .filter(|tag| !tag.is_empty() && !tag.starts_with(PROPAGATED_TAG_PREFIX))
+        .map(ToString::to_string)
+        .collect();
+    tags.extend(propagated);
+
+    trace_state
+        .insert(DATADOG_TRACE_STATE_KEY, tags.join(";"))
+        .unwrap_or_else(|_| trace_state.clone())
+}
+
+/// The product the trace originates from, recorded in the `dd` member of `trace_state`.
+pub(crate) fn origin_from_trace_state(trace_state: &TraceState) -> Option<String> {
+    trace_state
+        .get(DATADOG_TRACE_STATE_KEY)?
+        .split(';')
+        .find_map(|tag| tag.strip_prefix(ORIGIN_TAG))
+        .filter(|origin| !origin.is_empty())
+        .map(|origin| origin.replace('~', "="))
+}
+
+/// Records the `origin` of an `x-datadog-origin` header in the `dd` member of `trace_state`, as
+/// the Datadog tracers carry it in `tracestate`.
+pub(crate) fn trace_state_with_origin(trace_state: &TraceState, origin: &str) -> TraceState {
+    let origin: String = origin
+        .trim()
+        .chars()
+        .map(|c| match c {
+            '=' => '~',
+            ',' | ';' | '~' => '_',
+            c if c.is_ascii_graphic() => c,
+            _ => '_',
+        })
+        .collect();
+    if origin.is_empty() {
+        return trace_state.clone();
+    }
+
+    let mut tags: Vec<String> = trace_state
+        .get(DATADOG_TRACE_STATE_KEY)
+        .unwrap_or_default()
+        .split(';')
+        .filter(|tag| !tag.is_empty() && !tag.starts_with(ORIGIN_TAG))
+        .map(ToString::to_string)
+        .collect();
+    tags.push(format!("{ORIGIN_TAG}{origin}"));
+
+    trace_state
+        .insert(DATADOG_TRACE_STATE_KEY, tags.join(";"))
+        .unwrap_or_else(|_| trace_state.clone())
+}
+
+/// Force the sampling decision of the span in `cx`, e.g. to manually keep a trace that
+/// errored or manually drop a noisy one.
+///
+/// Span contexts are immutable, so this returns a new `Context` whose remote span context
+/// carries the decision. Spans started from it and headers injected from it use `priority`.
+#[must_use]
+pub fn with_sampling_priority(cx: &Context, priority: SamplingPriority) -> Context {
+    let span = cx.span();
+    let span_context = span.span_context();
+
+    cx.with_remote_span_context(SpanContext::new(
+        span_context.trace_id(),
+        span_context.span_id(),
+        priority.trace_flags(),
+        span_context.is_remote(),
+        trace_state_with_sampling_priority(span_context.trace_state(), priority),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_trace_state_round_trip() {
+        let trace_state =
+            TraceState::from_key_value(vec![("dd", "s:1;o:rum"), ("congo", "t61rcWkgMzE")])
+                .unwrap();
+
+        assert_eq!(
+            sampling_priority_from_trace_state(&trace_state),
+            Some(SamplingPriority::AutoKeep)
+        );
+
+        let trace_state =
+            trace_state_with_sampling_priority(&trace_state, SamplingPriority::UserReject);
+        assert_eq!(trace_state.get("dd"), Some("s:-1;o:rum"));
+        assert_eq!(trace_state.get("congo"), Some("t61rcWkgMzE"));
+        assert_eq!(
+            sampling_priority_from_trace_state(&trace_state),
+            Some(SamplingPriority::UserReject)
+        );
+    }
+
+    #[test]
+    fn test_propagated_tags() {
+        let trace_state = TraceState::from_key_value(vec![("dd", "s:2;t.old:1")]).unwrap();
+        let trace_state = trace_state_with_propagated_tags(
+            &trace_state,
+            "_dd.p.dm=-4, _dd.p.usr.id=dXNlcg==,other=1,_dd.p.bad=a;b",
+        );
+
+        assert_eq!(trace_state.get("dd"), Some("s:2;t.dm:-4;t.usr.id:dXNlcg~~"));
+        assert_eq!(
+            sampling_priority_from_trace_state(&trace_state),
+            Some(SamplingPriority::UserKeep)
+        );
+        assert_eq!(
+            propagated_tags_from_trace_state(&trace_state),
+            vec![
+                ("_dd.p.dm".to_string(), "-4".to_string()),
+                ("_dd.p.usr.id".to_string(), "dXNlcg==".to_string()),
+            ]
+        );
+        assert!(propagated_tags_from_trace_state(&TraceState::default()).is_empty());
+    }
+
+    #[test]
+    fn test_origin() {
+        let trace_state = TraceState::from_key_value(vec![("dd", "s:1;o:rum")]).unwrap();
+        assert_eq!(
+            origin_from_trace_state(&trace_state),
+            Some("rum".to_string())
+        );
+
+        let trace_state = trace_state_with_origin(&trace_state, "synthetics-browser");
+        assert_eq!(trace_state.get("dd"), Some("s:1;o:synthetics-browser"));
+        assert_eq!(
+            origin_from_trace_state(&trace_state),
+            Some("synthetics-browser".to_string())
+        );
+        assert_eq!(origin_from_trace_state(&TraceState::default()), None);
+    }
+}
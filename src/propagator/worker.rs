@@ -0,0 +1,110 @@
+use std::collections::HashMap;
+
+use opentelemetry::propagation::{Extractor, Injector};
+use worker::Headers;
+
+/// An [`Extractor`] over Cloudflare [`Headers`].
+///
+/// `Headers` only hands out owned values, so the entries are copied once when the extractor is
+/// created. Header names are lowercased by the runtime, which matches the propagator fields.
+///
+/// ## Example
+///
+/// ```no_run
+/// use opentelemetry::propagation::TextMapPropagator;
+/// use opentelemetry_datadog_cloudflare::{DatadogPropagator, WorkerHeadersExtractor};
+///
+/// fn parent_context(req: &worker::Request) -> opentelemetry::Context {
+///     DatadogPropagator::default().extract(&WorkerHeadersExtractor::from(req.headers()))
+/// }
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct WorkerHeadersExtractor {
+    headers: HashMap<String, String>,
+}
+
+impl From<&Headers> for WorkerHeadersExtractor {
+    fn from(headers: &Headers) -> Self {
+        WorkerHeadersExtractor::from_entries(headers.entries())
+    }
+}
+
+impl WorkerHeadersExtractor {
+    fn from_entries(entries: impl IntoIterator<Item = (String, String)>) -> Self {
+        WorkerHeadersExtractor {
+            headers: entries
+                .into_iter()
+                .map(|(name, value)| (name.to_ascii_lowercase(), value))
+                .collect(),
+        }
+    }
+}
+
+impl Extractor for WorkerHeadersExtractor {
+    fn get(&self, key: &str) -> Option<&str> {
+        self.headers
+            .get(&key.to_ascii_lowercase())
+            .map(String::as_str)
+    }
+
+    fn keys(&self) -> Vec<&str> {
+        self.headers.keys().map(String::as_str).collect()
+    }
+}
+
+/// An [`Injector`] writing into Cloudflare [`Headers`].
+///
+/// Values the runtime refuses (e.g. on immutable request headers) are silently skipped, as the
+/// `Injector` trait has no way to report errors.
+#[derive(Debug)]
+pub struct WorkerHeadersInjector<'a>(pub &'a mut Headers);
+
+impl<'a> Injector for WorkerHeadersInjector<'a> {
+    fn set(&mut self, key: &str, value: String) {
+        let _ = self.0.set(key, &value);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::DatadogPropagator;
+    use opentelemetry::propagation::TextMapPropagator;
+    use opentelemetry::trace::{
+        SpanContext, SpanId, TraceContextExt, TraceFlags, TraceId, TraceState,
+    };
+
+    #[test]
+    fn test_extract_worker_headers() {
+        let extractor = WorkerHeadersExtractor::from_entries([
+            ("x-datadog-trace-id".to_string(), "1234".to_string()),
+            ("X-Datadog-Parent-Id".to_string(), "12".to_string()),
+            ("x-datadog-sampling-priority".to_string(), "1".to_string()),
+        ]);
+
+        assert_eq!(extractor.get("X-Datadog-Trace-Id"), Some("1234"));
+        assert_eq!(extractor.get("x-datadog-parent-id"), Some("12"));
+        let mut keys = extractor.keys();
+        keys.sort_unstable();
+        assert_eq!(
+            keys,
+            vec![
+                "x-datadog-parent-id",
+                "x-datadog-sampling-priority",
+                "x-datadog-trace-id"
+            ]
+        );
+
+        let context = DatadogPropagator::default().extract(&extractor);
+        assert_eq!(
+            context.span().span_context(),
+            &SpanContext::new(
+                TraceId::from_u128(1234),
+                SpanId::from_u64(12),
+                TraceFlags::SAMPLED,
+                true,
+                TraceState::default(),
+            )
+        );
+    }
+}